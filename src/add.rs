@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use rusqlite::OptionalExtension;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    models::{CardMetadata, MergePolicy},
+    row_to_raw_hashmap,
+    validate::{self, Validator},
+    value::{self, FieldValue, Record},
+};
+
+// Interactive add: prompt for each input field, preview the derived fields, then upsert
+pub fn add_card(
+    conn: &mut rusqlite::Connection,
+    card_metadata: &CardMetadata,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let validator = Validator::new(card_metadata)?;
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    println!(
+        "Adding a card to '{}' (type \\n for a line break)",
+        card_metadata.name
+    );
+
+    let mut record = Record::new();
+    for field in &card_metadata.fields {
+        // Filled by SQLite, preprocess_data or fill_record
+        if field.is_auto_increment() || field.is_derived() || field.is_auto() {
+            continue;
+        }
+
+        // Blank means look it up
+        let label = match field.is_looked_up() {
+            true => format!(
+                "{} (blank: {})",
+                field.name,
+                field.lookup_sources().join(", ")
+            ),
+            false => field.name.clone(),
+        };
+
+        let value = loop {
+            match prompt(&mut input, &label)? {
+                None if field.is_not_null() && field.default_value().is_none() => {
+                    eprintln!("⚠️ '{}' is required", field.name)
+                }
+                None => break None,
+                // Typed now, so a bad number or flag is asked again
+                Some(value) => match FieldValue::parse(field, &value) {
+                    Ok(_) => break Some(value),
+                    Err(e) => eprintln!("⚠️ {}", e),
+                },
+            }
+        };
+
+        let Some(value) = value else {
+            continue;
+        };
+
+        // Warn early so the user can still back out
+        if field.is_key() {
+            let (sql, values) = card_metadata.get_card_by_key_sql(&field.get_alias(), &value);
+            let existing = conn
+                .query_row(&sql, &*values.as_params(), row_to_raw_hashmap)
+                .optional()?;
+
+            if let Some(existing) = existing {
+                println!("⚠️ '{}' already exists, saving will update it:", value);
+                print_card(card_metadata, &existing);
+            }
+        }
+
+        record.insert(field.name.clone(), JsonValue::String(value));
+    }
+
+    let (record, stamped) = card_metadata.fill_record(&record, None, &value::now());
+    let (fields, values, key) = card_metadata.get_data_from_record(&record)?;
+    let Some((fields, values)) = card_metadata.preprocess_data(conn, &fields, &values) else {
+        return Err("nothing to save".into());
+    };
+
+    let word = record
+        .get(&key.0)
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default();
+
+    // Preview
+    println!("Computed fields:");
+    for field in card_metadata
+        .fields
+        .iter()
+        .filter(|field| field.is_derived() || field.is_looked_up())
+    {
+        let value = fields
+            .iter()
+            .position(|alias| alias.0 == field.name)
+            .map(|pos| values[pos].to_string())
+            .unwrap_or_default();
+        println!("  {}: {}", field.name, value);
+    }
+
+    // Same rules as an import
+    if validate::report(word, &validator.check(&fields, &values)) {
+        return Err(format!("'{word}' rejected, not saved").into());
+    }
+
+    let confirm = prompt(&mut input, "Save? [y/N]")?.unwrap_or_default();
+    if !confirm.eq_ignore_ascii_case("y") {
+        println!("Discarded.");
+        return Ok(());
+    }
+
+    let mut merge = card_metadata.merge_policies(MergePolicy::Overwrite, &[])?;
+    merge.extend(stamped);
+    let (sql, params) =
+        card_metadata.insert_or_update_card_with_fields_sql(&key, &fields, &values, &merge);
+    conn.execute(sql.as_str(), &*params.as_params())?;
+
+    println!("✅ Saved '{}'", word);
+
+    Ok(())
+}
+
+// Read one line; a typed `\n` becomes a line break, blank means None
+fn prompt(
+    input: &mut impl BufRead,
+    label: &str,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    print!("{label}: ");
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err("input closed".into());
+    }
+
+    let value = line.trim_end_matches(['\r', '\n']).replace("\\n", "\n");
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(value))
+}
+
+// Print a DB row in template order
+fn print_card(card_metadata: &CardMetadata, card: &HashMap<String, String>) {
+    for field in &card_metadata.fields {
+        match card.get(&field.name) {
+            Some(value) if !value.is_empty() => println!("  {}: {}", field.name, value),
+            _ => {}
+        }
+    }
+}
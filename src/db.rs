@@ -2,159 +2,149 @@ use std::collections::HashMap;
 
 use crate::{
     ANKIKAJI_TABLE,
-    models::{CardMetadata, FieldSpec},
+    models::{
+        CardMetadata, ExportFormat, ExportProfile, ExportTarget, FieldSpec, FieldType, MergePolicy,
+    },
+    render::render_value,
+    value::FieldValue,
+};
+use rusqlite::types::Value;
+use sea_query::{
+    Alias, ColumnDef, Cond, Expr, ExprTrait, NullOrdering, OnConflict, Order, Query,
+    SqliteQueryBuilder, Table,
 };
-use sea_query::{Alias, ColumnDef, Expr, ExprTrait, OnConflict, Query, SqliteQueryBuilder, Table};
 use sea_query_rusqlite::{RusqliteBinder, RusqliteValues};
-
-// pub fn insert_or_update_card_ingore_null_values_tx(
-//     conn: &Transaction,
-//     card: Card,
-// ) -> rusqlite::Result<()> {
-//     let (sql, values) = Query::insert()
-//         .into_table(ANKIKAJI_TABLE.as_str())
-//         .columns([
-//             CardTable::Word,
-//             CardTable::WordWithReading,
-//             CardTable::Definition,
-//             CardTable::ExampleSentence,
-//             CardTable::SentenceWithReading,
-//             CardTable::SentenceTranslation,
-//             CardTable::Kanji,
-//             CardTable::WordAudio,
-//             CardTable::SentenceAudio,
-//             CardTable::Image,
-//             CardTable::Export,
-//         ])
-//         .values_panic([
-//             card.word.clone().into(),
-//             card.word_with_reading.clone().into(),
-//             card.definition.clone().into(),
-//             card.example_sentence.clone().into(),
-//             card.sentence_with_reading.clone().into(),
-//             card.translation_sentence.clone().into(),
-//             card.kanji.clone().into(),
-//             card.word_audio.clone().into(),
-//             card.sentence_audio.clone().into(),
-//             card.image.clone().into(),
-//             card.export.clone().into(),
-//         ])
-//         .on_conflict(
-//             OnConflict::column(CardTable::Word)
-//                 .values([
-//                     (
-//                         CardTable::WordWithReading,
-//                         Expr::col((Alias::new("excluded"), CardTable::WordWithReading))
-//                             .if_null(Expr::col(CardTable::WordWithReading)),
-//                     ),
-//                     (
-//                         CardTable::Definition,
-//                         Expr::col((Alias::new("excluded"), CardTable::Definition))
-//                             .if_null(Expr::col(CardTable::Definition)),
-//                     ),
-//                     (
-//                         CardTable::ExampleSentence,
-//                         Expr::col((Alias::new("excluded"), CardTable::ExampleSentence))
-//                             .if_null(Expr::col(CardTable::ExampleSentence)),
-//                     ),
-//                     (
-//                         CardTable::SentenceWithReading,
-//                         Expr::col((Alias::new("excluded"), CardTable::SentenceWithReading))
-//                             .if_null(Expr::col(CardTable::SentenceWithReading)),
-//                     ),
-//                     (
-//                         CardTable::SentenceTranslation,
-//                         Expr::col((Alias::new("excluded"), CardTable::SentenceTranslation))
-//                             .if_null(Expr::col(CardTable::SentenceTranslation)),
-//                     ),
-//                     (
-//                         CardTable::Kanji,
-//                         Expr::col((Alias::new("excluded"), CardTable::Kanji))
-//                             .if_null(Expr::col(CardTable::Kanji)),
-//                     ),
-//                     (
-//                         CardTable::WordAudio,
-//                         Expr::col((Alias::new("excluded"), CardTable::WordAudio))
-//                             .if_null(Expr::col(CardTable::WordAudio)),
-//                     ),
-//                     (
-//                         CardTable::SentenceAudio,
-//                         Expr::col((Alias::new("excluded"), CardTable::SentenceAudio))
-//                             .if_null(Expr::col(CardTable::SentenceAudio)),
-//                     ),
-//                     (
-//                         CardTable::Image,
-//                         Expr::col((Alias::new("excluded"), CardTable::Image))
-//                             .if_null(Expr::col(CardTable::Image)),
-//                     ),
-//                     (
-//                         CardTable::Export,
-//                         Expr::col((Alias::new("excluded"), CardTable::Export))
-//                             .if_null(Expr::col(CardTable::Export)),
-//                     ),
-//                 ])
-//                 .to_owned(),
-//         )
-//         .build_rusqlite(SqliteQueryBuilder);
-
-//     conn.execute(&sql, &*values.as_params())?;
-//     Ok(())
-// }
-
-// pub fn get_card_by_word(conn: &rusqlite::Connection, word: &str) -> rusqlite::Result<Option<Card>> {
-//     let (sql, values) = Query::select()
-//         .columns([
-//             CardTable::Index,
-//             CardTable::Word,
-//             CardTable::WordWithReading,
-//             CardTable::Definition,
-//             CardTable::ExampleSentence,
-//             CardTable::SentenceWithReading,
-//             CardTable::SentenceTranslation,
-//             CardTable::Kanji,
-//             CardTable::WordAudio,
-//             CardTable::SentenceAudio,
-//             CardTable::Image,
-//             CardTable::Export,
-//         ])
-//         .from(ANKIKAJI_TABLE.as_str())
-//         .and_where(Expr::col(CardTable::Word).eq(word))
-//         .build_rusqlite(SqliteQueryBuilder);
-
-//     let mut stmt = conn.prepare(&sql)?;
-//     let card_opt = stmt
-//         .query_row(&*values.as_params(), |row| Card::from_row(row))
-//         .optional()?;
-
-//     Ok(card_opt)
-// }
+use serde_json::Value as JsonValue;
 
 // Helper FN
 
 impl CardMetadata {
-    pub fn export_csv(
+    // One exported row: an array of rendered strings, or a typed object for JSON
+    pub fn export_row(
         &self,
-        cards: &[HashMap<String, String>],
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut wtr = csv::Writer::from_path(filename)?;
-        for card in cards {
-            for key in self.fields.iter().as_ref() {
-                if let Some(value) = card.get(&key.name) {
-                    wtr.write_field(value)?;
-                } else {
-                    wtr.write_field("")?;
+        profile: &ExportProfile,
+        format: ExportFormat,
+        card: &HashMap<String, Value>,
+    ) -> Result<JsonValue, Box<dyn std::error::Error>> {
+        let defaults = self.render.get(&format).cloned().unwrap_or_default();
+        let mut values = Vec::new();
+        let mut object = serde_json::Map::new();
+
+        for column in &profile.fields {
+            let field_type = self
+                .fields
+                .iter()
+                .find(|field| field.name == column.field)
+                .map(|field| field.field_type.clone())
+                .unwrap_or_default();
+            let value = card.get(&column.field).unwrap_or(&Value::Null);
+            let rendered = render_value(value, &field_type, &column.render().merge(&defaults))?;
+
+            if !format.is_json() {
+                values.push(JsonValue::String(rendered));
+                continue;
+            }
+
+            let typed = match (value, &field_type) {
+                (Value::Null, _) => JsonValue::Null,
+                (Value::Integer(i), FieldType::Boolean) => JsonValue::Bool(*i != 0),
+                (Value::Integer(i), _) => JsonValue::from(*i),
+                (Value::Real(f), _) => JsonValue::from(*f),
+                // Stored as JSON text, exported as is
+                (Value::Text(t), FieldType::List | FieldType::Json) => {
+                    serde_json::from_str(t).unwrap_or(JsonValue::String(rendered))
                 }
+                _ => JsonValue::String(rendered),
+            };
+            object.insert(column.get_column().to_string(), typed);
+        }
+
+        if format.is_json() {
+            return Ok(JsonValue::Object(object));
+        }
+        Ok(JsonValue::Array(values))
+    }
+}
+
+pub fn write_rows(
+    header: Option<&[String]>,
+    rows: &[JsonValue],
+    filename: &str,
+    format: ExportFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        ExportFormat::Csv | ExportFormat::Tsv => {
+            let delimiter = match format {
+                ExportFormat::Tsv => b'\t',
+                _ => b',',
+            };
+
+            let mut wtr = csv::WriterBuilder::new()
+                .delimiter(delimiter)
+                .from_path(filename)?;
+            if let Some(header) = header {
+                wtr.write_record(header)?;
+            }
+            for row in rows {
+                wtr.write_record(row_strings(row))?;
             }
-            wtr.write_record(None::<&[u8]>)?;
+            wtr.flush()?;
+        }
+        ExportFormat::Text => {
+            let mut out = String::new();
+            for row in rows {
+                let values: Vec<String> = row_strings(row)
+                    .into_iter()
+                    .filter(|value| !value.is_empty())
+                    .collect();
+                out.push_str(&values.join(" — "));
+                out.push('\n');
+            }
+            std::fs::write(filename, out)?;
+        }
+        ExportFormat::Json => {
+            std::fs::write(filename, serde_json::to_string_pretty(rows)?)?;
+        }
+        ExportFormat::Jsonl => {
+            let mut out = String::new();
+            for row in rows {
+                out.push_str(&serde_json::to_string(row)?);
+                out.push('\n');
+            }
+            std::fs::write(filename, out)?;
         }
-        wtr.flush()?;
-        Ok(())
+    }
+    Ok(())
+}
+
+// Row values as text for the tabular formats
+fn row_strings(row: &JsonValue) -> Vec<String> {
+    let values: Vec<&JsonValue> = match row {
+        JsonValue::Array(values) => values.iter().collect(),
+        JsonValue::Object(object) => object.values().collect(),
+        value => vec![value],
+    };
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            JsonValue::Null => String::new(),
+            JsonValue::String(s) => s.clone(),
+            value => value.to_string(),
+        })
+        .collect()
+}
+
+// Exported state column of a target, `Export` for the default one
+#[inline]
+pub fn export_alias(target: &str) -> Alias {
+    match target {
+        "default" => Alias::new("Export"),
+        name => Alias::new(format!("Export_{name}")),
     }
 }
 
 // DB OP
-// ToDo: Use rusqlite value instead of string
 
 impl CardMetadata {
     pub fn create_table_sql(&self) -> String {
@@ -166,13 +156,55 @@ impl CardMetadata {
         self.fields.iter().for_each(|field| {
             temp.col(field.get_col());
         });
-        // Export Col
-        temp.col(ColumnDef::new(Alias::new("Export")).boolean().default(0));
+        // Export Col, one per target
+        for export in self.get_export_aliases() {
+            temp.col(ColumnDef::new(export).boolean().default(0));
+        }
 
         temp.build(SqliteQueryBuilder)
     }
 
-    pub fn insert_card_sql(&self, values: &Vec<String>) -> (String, RusqliteValues) {
+    pub fn get_export_aliases(&self) -> Vec<Alias> {
+        std::iter::once("default")
+            .chain(
+                self.targets
+                    .iter()
+                    .map(|target| target.name.as_str())
+                    .filter(|name| *name != "default"),
+            )
+            .map(export_alias)
+            .collect()
+    }
+
+    // Fields and targets added to the template after the table was created
+    pub fn add_missing_columns_sql(&self, existing: &[String]) -> Result<Vec<String>, String> {
+        let missing = |name: &str| !existing.iter().any(|col| col == name);
+
+        let mut columns = Vec::new();
+        for field in self.fields.iter().filter(|field| missing(&field.name)) {
+            columns.push(field.get_added_col()?);
+        }
+        for export in self
+            .get_export_aliases()
+            .into_iter()
+            .filter(|export| missing(&export.0))
+        {
+            columns.push(ColumnDef::new(export).boolean().default(0).to_owned());
+        }
+
+        Ok(columns
+            .into_iter()
+            .map(|mut col| {
+                Table::alter()
+                    .table(ANKIKAJI_TABLE.as_str())
+                    .add_column(&mut col)
+                    .to_owned()
+                    .build(SqliteQueryBuilder)
+            })
+            .collect())
+    }
+
+    pub fn insert_card_sql(&self, values: &Vec<FieldValue>) -> (String, RusqliteValues) {
         let temp = Query::insert()
             .into_table(ANKIKAJI_TABLE.as_str())
             .columns(get_all_aliases(&self.fields))
@@ -185,67 +217,177 @@ impl CardMetadata {
     pub fn insert_or_update_card_sql(
         &self,
         key: &Alias,
-        values: &Vec<String>,
+        values: &Vec<FieldValue>,
     ) -> (String, RusqliteValues) {
+        let updated = self
+            .fields
+            .iter()
+            .map(FieldSpec::get_alias)
+            .filter(|alias| alias.0 != key.0)
+            .map(|alias| (alias, MergePolicy::Overwrite));
+
         let temp = Query::insert()
             .into_table(ANKIKAJI_TABLE.as_str())
             .columns(get_all_aliases(&self.fields).chain(std::iter::once(Alias::new("Export"))))
             .values_panic(get_all_values(values).chain(std::iter::once(0.into())))
-            .on_conflict(
-                OnConflict::column(key.clone())
-                    .update_columns(get_all_aliases(&self.fields).filter(|alias| alias.0 != key.0))
-                    .update_column(Alias::new("Export"))
-                    .to_owned(),
-            )
+            .on_conflict(self.upsert_on_conflict(key, updated))
             .to_owned();
 
         temp.build_rusqlite(SqliteQueryBuilder)
     }
 
+    // Fields missing from `merge` are overwritten
     pub fn insert_or_update_card_with_fields_sql(
         &self,
         key: &Alias,
         fields: &Vec<Alias>,
-        values: &Vec<String>,
+        values: &Vec<FieldValue>,
+        merge: &HashMap<String, MergePolicy>,
     ) -> (String, RusqliteValues) {
         let mut cols = fields.clone();
+
+        let updated: Vec<(Alias, MergePolicy)> = cols
+            .iter()
+            .filter(|alias| alias.0 != key.0)
+            .map(|alias| {
+                let policy = merge.get(&alias.0).copied().unwrap_or_default();
+                (alias.clone(), policy)
+            })
+            .collect();
+
         cols.push(Alias::new("Export"));
 
         let temp = Query::insert()
             .into_table(ANKIKAJI_TABLE.as_str())
-            .columns(cols.clone())
+            .columns(cols)
             .values_panic(get_all_values(values).chain(std::iter::once(0.into())))
-            .on_conflict(
-                OnConflict::column(key.clone())
-                    .update_columns(cols.iter().filter(|alias| alias.0 != key.0).cloned())
-                    .to_owned(),
-            )
+            .on_conflict(self.upsert_on_conflict(key, updated.into_iter()))
             .to_owned();
 
         temp.build_rusqlite(SqliteQueryBuilder)
     }
 
-    pub fn get_unexported_cards_sql(&self) -> (String, RusqliteValues) {
+    // Merge the given columns, every target keeps its exported state unless something changed
+    fn upsert_on_conflict(
+        &self,
+        key: &Alias,
+        updated: impl Iterator<Item = (Alias, MergePolicy)>,
+    ) -> OnConflict {
+        let (touched, updated): (Vec<_>, Vec<_>) =
+            updated.partition(|(_, policy)| *policy == MergePolicy::Touch);
+
+        let mut merged: Vec<(Alias, Expr)> = updated
+            .into_iter()
+            .map(|(col, policy)| {
+                let list = self.fields.iter().any(|field| {
+                    field.name == col.0 && matches!(field.field_type, FieldType::List)
+                });
+                let expr = merge_expr(&col, policy, list);
+                (col, expr)
+            })
+            .collect();
+
+        // Stamps like `source` or `created_at` aren't a change of the card
+        let content: Vec<(Alias, Expr)> = merged
+            .iter()
+            .filter(|(col, _)| {
+                !self
+                    .fields
+                    .iter()
+                    .any(|field| field.name == col.0 && field.is_auto())
+            })
+            .cloned()
+            .collect();
+
+        for (col, _) in touched {
+            let kept = unchanged(content.iter().cloned()).add(Expr::col(col.clone()).is_not_null());
+            let expr = Expr::case(kept, Expr::col(col.clone()))
+                .finally(Expr::col((Alias::new("excluded"), col.clone())))
+                .into();
+            merged.push((col, expr));
+        }
+
+        let mut on_conflict = OnConflict::column(key.clone()).values(merged).to_owned();
+
+        for export in self.get_export_aliases() {
+            on_conflict.value(
+                export.clone(),
+                export_unless_changed(export, content.iter().cloned()),
+            );
+        }
+
+        on_conflict
+    }
+
+    pub fn get_card_by_key_sql(&self, key: &Alias, value: &str) -> (String, RusqliteValues) {
         let temp = Query::select()
             .columns(get_all_aliases(&self.fields))
             .column(Alias::new("Export"))
             .from(ANKIKAJI_TABLE.as_str())
-            .and_where(Expr::col(Alias::new("Export")).eq(false))
+            .and_where(Expr::col(key.clone()).eq(value))
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    // Oldest first, so `dedupe` knows which card came first
+    pub fn get_all_cards_sql(&self) -> (String, RusqliteValues) {
+        let order = match self.fields.iter().find(|field| field.is_primary_key()) {
+            Some(field) => field.get_alias(),
+            None => Alias::new("rowid"),
+        };
+        let temp = Query::select()
+            .columns(get_all_aliases(&self.fields))
+            .column(Alias::new("Export"))
+            .from(ANKIKAJI_TABLE.as_str())
+            .order_by(order, Order::Asc)
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn delete_card_by_key_sql(&self, key: &Alias, value: &str) -> (String, RusqliteValues) {
+        let temp = Query::delete()
+            .from_table(ANKIKAJI_TABLE.as_str())
+            .and_where(Expr::col(key.clone()).eq(value))
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn get_unexported_cards_sql(&self, target: &ExportTarget) -> (String, RusqliteValues) {
+        let mut temp = Query::select()
+            .columns(get_all_aliases(&self.fields))
+            .from(ANKIKAJI_TABLE.as_str())
+            .and_where(Expr::col(export_alias(&target.name)).eq(false))
             .to_owned();
 
+        // Words missing from the list are kept
+        if let (Some(max_rank), Some(field)) = (target.max_rank, self.get_frequency_field()) {
+            temp.cond_where(
+                Cond::any()
+                    .add(Expr::col(field.get_alias()).is_null())
+                    .add(Expr::col(field.get_alias()).lte(max_rank)),
+            );
+        }
+        if let Some(sort) = &target.sort {
+            temp.order_by_with_nulls(Alias::new(sort.as_str()), Order::Asc, NullOrdering::Last);
+        }
+
         temp.build_rusqlite(SqliteQueryBuilder)
     }
 
     pub fn mark_unexported_cards_tx(
         &self,
         key: &Alias,
+        target: &str,
         words: &[String],
     ) -> Vec<(String, RusqliteValues)> {
         let mut tx = Vec::<(String, RusqliteValues)>::new();
         for word in words {
             let temp = Query::update()
                 .table(ANKIKAJI_TABLE.as_str())
-                .value(Alias::new("Export"), true)
+                .value(export_alias(target), true)
                 .and_where(Expr::col(key.clone()).eq(word))
                 .to_owned();
 
@@ -256,9 +398,306 @@ impl CardMetadata {
     }
 }
 
+// Export history
+
+#[inline]
+pub fn export_batch_table() -> Alias {
+    Alias::new(format!("{}_export_batch", ANKIKAJI_TABLE.as_str()))
+}
+
+#[inline]
+fn export_card_table() -> Alias {
+    Alias::new(format!("{}_export_card", ANKIKAJI_TABLE.as_str()))
+}
+
+impl CardMetadata {
+    pub fn create_export_history_sql(&self) -> Vec<String> {
+        let batch = Table::create()
+            .table(export_batch_table())
+            .if_not_exists()
+            .col(
+                ColumnDef::new(Alias::new("id"))
+                    .integer()
+                    .not_null()
+                    .primary_key()
+                    .auto_increment(),
+            )
+            .col(
+                ColumnDef::new(Alias::new("created_at"))
+                    .date_time()
+                    .not_null()
+                    .default(Expr::current_timestamp()),
+            )
+            .col(ColumnDef::new(Alias::new("target")).string().not_null())
+            .col(ColumnDef::new(Alias::new("file")).string().not_null())
+            .col(
+                ColumnDef::new(Alias::new("card_count"))
+                    .integer()
+                    .not_null(),
+            )
+            // What was written, so regenerate doesn't follow later template edits
+            .col(ColumnDef::new(Alias::new("format")).string())
+            .col(ColumnDef::new(Alias::new("header")).text())
+            .to_owned();
+
+        let card = Table::create()
+            .table(export_card_table())
+            .if_not_exists()
+            .col(ColumnDef::new(Alias::new("batch_id")).integer().not_null())
+            .col(ColumnDef::new(Alias::new("card_key")).string().not_null())
+            .col(
+                ColumnDef::new(Alias::new("content_hash"))
+                    .string()
+                    .not_null(),
+            )
+            .col(ColumnDef::new(Alias::new("content")).text().not_null())
+            .to_owned();
+
+        vec![
+            batch.build(SqliteQueryBuilder),
+            card.build(SqliteQueryBuilder),
+        ]
+    }
+
+    // Batches recorded before `format` and `header` were kept have them NULL
+    pub fn add_export_batch_columns_sql(&self, existing: &[String]) -> Vec<String> {
+        let missing = |name: &str| !existing.iter().any(|col| col == name);
+
+        let mut columns = Vec::new();
+        if missing("format") {
+            columns.push(ColumnDef::new(Alias::new("format")).string().to_owned());
+        }
+        if missing("header") {
+            columns.push(ColumnDef::new(Alias::new("header")).text().to_owned());
+        }
+
+        columns
+            .into_iter()
+            .map(|mut col| {
+                Table::alter()
+                    .table(export_batch_table())
+                    .add_column(&mut col)
+                    .to_owned()
+                    .build(SqliteQueryBuilder)
+            })
+            .collect()
+    }
+
+    pub fn insert_export_batch_sql(
+        &self,
+        target: &str,
+        file: &str,
+        card_count: usize,
+        format: ExportFormat,
+        // JSON array of the column names
+        header: Option<&str>,
+    ) -> (String, RusqliteValues) {
+        let temp = Query::insert()
+            .into_table(export_batch_table())
+            .columns([
+                Alias::new("target"),
+                Alias::new("file"),
+                Alias::new("card_count"),
+                Alias::new("format"),
+                Alias::new("header"),
+            ])
+            .values_panic([
+                target.into(),
+                file.into(),
+                (card_count as i64).into(),
+                format.extension().into(),
+                header.into(),
+            ])
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn insert_export_card_sql(
+        &self,
+        batch_id: i64,
+        card_key: &str,
+        content_hash: &str,
+        content: &str,
+    ) -> (String, RusqliteValues) {
+        let temp = Query::insert()
+            .into_table(export_card_table())
+            .columns([
+                Alias::new("batch_id"),
+                Alias::new("card_key"),
+                Alias::new("content_hash"),
+                Alias::new("content"),
+            ])
+            .values_panic([
+                batch_id.into(),
+                card_key.into(),
+                content_hash.into(),
+                content.into(),
+            ])
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn get_export_batches_sql(&self) -> (String, RusqliteValues) {
+        let temp = Query::select()
+            .columns([
+                Alias::new("id"),
+                Alias::new("created_at"),
+                Alias::new("target"),
+                Alias::new("file"),
+                Alias::new("card_count"),
+            ])
+            .from(export_batch_table())
+            .order_by(Alias::new("id"), Order::Asc)
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn get_export_batch_sql(&self, batch_id: i64) -> (String, RusqliteValues) {
+        let temp = Query::select()
+            .columns([
+                Alias::new("file"),
+                Alias::new("target"),
+                Alias::new("format"),
+                Alias::new("header"),
+            ])
+            .from(export_batch_table())
+            .and_where(Expr::col(Alias::new("id")).eq(batch_id))
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    pub fn get_export_batch_contents_sql(&self, batch_id: i64) -> (String, RusqliteValues) {
+        let temp = Query::select()
+            .column(Alias::new("content"))
+            .from(export_card_table())
+            .and_where(Expr::col(Alias::new("batch_id")).eq(batch_id))
+            .order_by(Alias::new("rowid"), Order::Asc)
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    // Keys exported to the target at least once
+    pub fn get_exported_keys_sql(&self, target: &str) -> (String, RusqliteValues) {
+        let batches = Query::select()
+            .column(Alias::new("id"))
+            .from(export_batch_table())
+            .and_where(Expr::col(Alias::new("target")).eq(target))
+            .to_owned();
+
+        let temp = Query::select()
+            .distinct()
+            .column(Alias::new("card_key"))
+            .from(export_card_table())
+            .and_where(Expr::col(Alias::new("batch_id")).in_subquery(batches))
+            .to_owned();
+
+        temp.build_rusqlite(SqliteQueryBuilder)
+    }
+
+    // Cards of the batch count as unexported again, the batch is forgotten
+    pub fn rollback_export_batch_tx(
+        &self,
+        key: &Alias,
+        target: &str,
+        batch_id: i64,
+    ) -> Vec<(String, RusqliteValues)> {
+        let keys = Query::select()
+            .column(Alias::new("card_key"))
+            .from(export_card_table())
+            .and_where(Expr::col(Alias::new("batch_id")).eq(batch_id))
+            .to_owned();
+
+        let unexport = Query::update()
+            .table(ANKIKAJI_TABLE.as_str())
+            .value(export_alias(target), false)
+            .and_where(Expr::col(key.clone()).in_subquery(keys))
+            .to_owned();
+
+        let cards = Query::delete()
+            .from_table(export_card_table())
+            .and_where(Expr::col(Alias::new("batch_id")).eq(batch_id))
+            .to_owned();
+
+        let batch = Query::delete()
+            .from_table(export_batch_table())
+            .and_where(Expr::col(Alias::new("id")).eq(batch_id))
+            .to_owned();
+
+        vec![
+            unexport.build_rusqlite(SqliteQueryBuilder),
+            cards.build_rusqlite(SqliteQueryBuilder),
+            batch.build_rusqlite(SqliteQueryBuilder),
+        ]
+    }
+}
+
+// Keep the export state when the upsert changes nothing, otherwise unexport
+fn export_unless_changed(export: Alias, merged: impl Iterator<Item = (Alias, Expr)>) -> Expr {
+    Expr::case(unchanged(merged), Expr::col(export))
+        .finally(0)
+        .into()
+}
+
+// Every column keeps its stored value after the merge
+fn unchanged(merged: impl Iterator<Item = (Alias, Expr)>) -> Cond {
+    merged.fold(Cond::all(), |cond, (col, expr)| {
+        cond.add(Expr::col(col).is(expr))
+    })
+}
+
+// New value of a column on conflict, from the stored one and `excluded`
+fn merge_expr(col: &Alias, policy: MergePolicy, list: bool) -> Expr {
+    let stored = || Expr::col(col.clone());
+    let incoming = || Expr::col((Alias::new("excluded"), col.clone()));
+    let is_empty = |expr: Expr| Cond::any().add(expr.clone().is_null()).add(expr.eq(""));
+
+    match policy {
+        // Touch is resolved by the caller, it depends on the other columns
+        MergePolicy::Overwrite | MergePolicy::Touch => incoming(),
+        // Only an explicit null or blank empties it, other input keeps the stored value
+        MergePolicy::Clear => Expr::case(is_empty(incoming()), Expr::cust("NULL"))
+            .finally(stored())
+            .into(),
+        MergePolicy::Fill => Expr::case(is_empty(stored()), incoming())
+            .finally(stored())
+            .into(),
+        MergePolicy::KeepIfEmpty => Expr::case(is_empty(incoming()), stored())
+            .finally(incoming())
+            .into(),
+        // Items missing from the stored list are added at the end
+        MergePolicy::Append if list => Expr::case(is_empty(incoming()), stored())
+            .case(is_empty(stored()), incoming())
+            .finally(Expr::cust_with_exprs(
+                "(SELECT json_group_array(value) FROM \
+                 (SELECT value FROM json_each($1) \
+                  UNION ALL SELECT value FROM json_each($2) \
+                  WHERE value NOT IN (SELECT value FROM json_each($1))))",
+                [stored(), incoming()],
+            ))
+            .into(),
+        // One entry per line, re-importing the same text adds nothing
+        MergePolicy::Append => Expr::case(is_empty(incoming()), stored())
+            .case(is_empty(stored()), incoming())
+            .case(
+                Expr::cust_with_exprs("instr($1, $2) > 0", [stored(), incoming()]),
+                stored(),
+            )
+            .finally(Expr::cust_with_exprs(
+                "$1 || char(10) || $2",
+                [stored(), incoming()],
+            ))
+            .into(),
+    }
+}
+
 #[inline]
-fn get_all_values(values: &Vec<String>) -> impl Iterator<Item = Expr> + '_ {
-    values.iter().map(|value| value.into())
+fn get_all_values(values: &Vec<FieldValue>) -> impl Iterator<Item = Expr> + '_ {
+    values.iter().map(Expr::from)
 }
 
 #[inline]
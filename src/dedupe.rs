@@ -0,0 +1,347 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use sea_query::Alias;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    annotate,
+    models::{CardMetadata, RubyFormat},
+    render, row_to_raw_hashmap, validate,
+    value::FieldValue,
+};
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum MergeStrategy {
+    /// Only print the candidate groups
+    #[default]
+    Report,
+    /// Keep the oldest card as is
+    First,
+    /// Keep the newest card as is
+    Last,
+    /// Keep the oldest card, fill its empty fields from the others
+    Fill,
+    /// Pick every conflicting field by hand
+    Ask,
+}
+
+// NFKC, no whitespace, katakana folded to hiragana
+pub fn normalize(text: &str) -> String {
+    text.nfkc()
+        .filter(|c| !c.is_whitespace())
+        .map(katakana_to_hiragana)
+        .collect()
+}
+
+#[inline]
+fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+// Reading from autoruby: " 掲[かか] げる" → "かかげる"
+#[inline]
+fn reading(text: &str) -> String {
+    render::ruby(&annotate(text), RubyFormat::Kana)
+}
+
+// Every form two words are compared by
+pub fn normalized_forms(word: &str) -> Vec<String> {
+    let surface = normalize(word);
+    if surface.is_empty() {
+        return Vec::new();
+    }
+
+    let reading = normalize(&reading(&surface));
+    if reading.is_empty() || reading == surface {
+        return vec![surface];
+    }
+
+    vec![surface, reading]
+}
+
+// Import-time check against the DB and earlier records of the same file
+#[derive(Default)]
+pub struct DuplicateIndex {
+    seen: HashMap<String, String>,
+}
+
+impl DuplicateIndex {
+    pub fn new<'a>(words: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut index = DuplicateIndex::default();
+        for word in words {
+            index.check(word);
+        }
+        index
+    }
+
+    // Returns a different word sharing a normalized form, then remembers this one
+    pub fn check(&mut self, word: &str) -> Option<String> {
+        let mut found = None;
+        for form in normalized_forms(word) {
+            match self.seen.get(&form) {
+                Some(other) if other != word => {
+                    found.get_or_insert_with(|| other.clone());
+                }
+                Some(_) => {}
+                None => {
+                    self.seen.insert(form, word.to_string());
+                }
+            }
+        }
+        found
+    }
+}
+
+// Group indexes of words sharing any normalized form
+pub fn find_duplicate_groups(words: &[&str]) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..words.len()).collect();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (i, word) in words.iter().enumerate() {
+        for form in normalized_forms(word) {
+            match seen.get(&form) {
+                Some(&j) => union(&mut parent, i, j),
+                None => {
+                    seen.insert(form, i);
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..words.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() > 1).collect();
+    groups.sort();
+    groups
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    if a != b {
+        parent[a.max(b)] = a.min(b);
+    }
+}
+
+pub fn dedupe(
+    conn: &mut rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    strategy: MergeStrategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (sql, values) = card_metadata.get_all_cards_sql();
+    let mut stmt = conn.prepare(&sql)?;
+    let cards = stmt
+        .query_map(&*values.as_params(), row_to_raw_hashmap)?
+        .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
+    drop(stmt);
+
+    let key = card_metadata.get_main_key();
+    let words: Vec<&str> = cards
+        .iter()
+        .map(|card| card.get(&key.name).map(String::as_str).unwrap_or(""))
+        .collect();
+
+    let groups = find_duplicate_groups(&words);
+    if groups.is_empty() {
+        println!("No duplicates found.");
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut merged_count = 0;
+
+    let tx = conn.transaction()?;
+    for group in &groups {
+        let group_words: Vec<&str> = group.iter().map(|&i| words[i]).collect();
+        println!("🔁 Possible duplicates: {}", group_words.join(", "));
+
+        let group: Vec<&HashMap<String, String>> = group.iter().map(|&i| &cards[i]).collect();
+        let merged = match strategy {
+            MergeStrategy::Report => continue,
+            MergeStrategy::First => group[0].clone(),
+            MergeStrategy::Last => group[group.len() - 1].clone(),
+            MergeStrategy::Fill => fill_merge(card_metadata, &group),
+            MergeStrategy::Ask => match ask_merge(&mut input, card_metadata, &group)? {
+                Some(merged) => merged,
+                None => continue,
+            },
+        };
+
+        let kept = merged.get(&key.name).cloned().unwrap_or_default();
+
+        // Only rewrite the kept row when its content changed
+        let original = group.iter().find(|card| card.get(&key.name) == Some(&kept));
+        if original.is_none_or(|original| **original != merged) {
+            let (fields, values): (Vec<Alias>, Vec<FieldValue>) = card_metadata
+                .fields
+                .iter()
+                .filter(|field| !field.is_auto_increment())
+                .filter_map(|field| {
+                    let value = merged.get(&field.name).filter(|value| !value.is_empty())?;
+                    // Stored as `<img src="…">`, preprocess_data wraps the name again
+                    let value = match field.is_image() {
+                        true => validate::media_path(value),
+                        false => value,
+                    };
+                    Some(FieldValue::parse(field, value).map(|value| (field.get_alias(), value)))
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .unzip();
+
+            // Derived fields follow the merged values
+            let Some((fields, values)) = card_metadata.preprocess_data(&tx, &fields, &values)
+            else {
+                eprintln!("⚠️ '{}' not merged: nothing to store", kept);
+                continue;
+            };
+
+            let (sql, params) = card_metadata.insert_or_update_card_with_fields_sql(
+                &key.get_alias(),
+                &fields,
+                &values,
+                // The merged row is final
+                &HashMap::new(),
+            );
+            tx.execute(sql.as_str(), &*params.as_params())?;
+        }
+
+        for word in group_words.iter().filter(|word| **word != kept) {
+            let (sql, params) = card_metadata.delete_card_by_key_sql(&key.get_alias(), word);
+            tx.execute(sql.as_str(), &*params.as_params())?;
+        }
+
+        println!("✅ Kept '{}'", kept);
+        merged_count += 1;
+    }
+    tx.commit()?;
+
+    println!(
+        "Found {} duplicate groups, merged {}",
+        groups.len(),
+        merged_count
+    );
+
+    Ok(())
+}
+
+fn fill_merge(
+    card_metadata: &CardMetadata,
+    group: &[&HashMap<String, String>],
+) -> HashMap<String, String> {
+    let mut merged = group[0].clone();
+    for field in &card_metadata.fields {
+        // Computed again from the merged card
+        if field.is_derived() || field.is_looked_up() {
+            continue;
+        }
+
+        let current = merged.get(&field.name).map(String::as_str).unwrap_or("");
+        if !current.is_empty() {
+            continue;
+        }
+
+        if let Some(value) = group
+            .iter()
+            .filter_map(|card| card.get(&field.name))
+            .find(|value| !value.is_empty())
+        {
+            merged.insert(field.name.clone(), value.clone());
+        }
+    }
+    merged
+}
+
+// The key decides which card is kept; derived fields follow it
+fn ask_merge(
+    input: &mut impl BufRead,
+    card_metadata: &CardMetadata,
+    group: &[&HashMap<String, String>],
+) -> Result<Option<HashMap<String, String>>, Box<dyn std::error::Error>> {
+    let key = card_metadata.get_main_key();
+    let mut merged = HashMap::new();
+
+    let key_choice = match choose(input, &key.name, group)? {
+        Some(choice) => choice,
+        None => return Ok(None),
+    };
+    let kept = group
+        .iter()
+        .find(|card| card.get(&key.name) == Some(&key_choice))
+        .unwrap();
+
+    for field in &card_metadata.fields {
+        let value = if field.is_key() {
+            Some(key_choice.clone())
+        } else if field.is_auto_increment() || field.is_derived() || field.is_auto() {
+            kept.get(&field.name).cloned()
+        } else {
+            choose(input, &field.name, group)?
+        };
+
+        if let Some(value) = value {
+            merged.insert(field.name.clone(), value);
+        }
+    }
+
+    Ok(Some(merged))
+}
+
+// Prompt only when the cards disagree; blank answer skips the group
+fn choose(
+    input: &mut impl BufRead,
+    name: &str,
+    group: &[&HashMap<String, String>],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut options: Vec<&String> = Vec::new();
+    for value in group.iter().filter_map(|card| card.get(name)) {
+        if !value.is_empty() && !options.contains(&value) {
+            options.push(value);
+        }
+    }
+
+    match options.len() {
+        0 => return Ok(Some(String::new())),
+        1 => return Ok(Some(options[0].clone())),
+        _ => {}
+    }
+
+    println!("  {name}:");
+    for (i, option) in options.iter().enumerate() {
+        println!("    [{}] {}", i + 1, option.replace('\n', " / "));
+    }
+
+    loop {
+        print!("  pick 1-{} (blank to skip group): ", options.len());
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(None);
+        }
+
+        match line.trim().parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => return Ok(Some(options[n - 1].clone())),
+            _ => eprintln!("⚠️ Invalid choice"),
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use std::{io::BufReader, path::Path};
+
+use quick_xml::{Reader, events::Event};
+use rusqlite::{Connection, OptionalExtension, params};
+use sea_query::{
+    Alias, ColumnDef, Cond, Expr, ExprTrait, Func, Index, Order, Query, SqliteQueryBuilder, Table,
+};
+use sea_query_rusqlite::RusqliteBinder;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    examples, frequency, kanjidic,
+    models::{DictionaryEntry, FieldSpec},
+    pitch, yomitan,
+};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DictAction {
+    /// Index JMdict / JMdict_e XML, or jmdict-simplified JSON
+    ImportJmdict {
+        file: String,
+        /// Gloss languages to keep (e.g. eng, ger), all if not given
+        #[arg(short, long)]
+        lang: Vec<String>,
+    },
+    /// Index a Yomitan / Yomichan term dictionary zip
+    ImportYomitan {
+        file: String,
+        /// Name for `Dictionary:`, the dictionary's title if not given
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Index a KANJIDIC2 XML for `Kanji Breakdown` fields
+    ImportKanjidic {
+        file: String,
+        /// Meaning language (ISO 639-1: en, fr, es, pt)
+        #[arg(short, long, default_value = "en")]
+        lang: String,
+    },
+    /// Index a Kanjium style accents TSV for `Pitch Accent` fields
+    ImportPitch { file: String },
+    /// Index Tatoeba sentence pairs for `examples`
+    ImportTatoeba { file: String },
+    /// Index a frequency list, most frequent word first
+    ImportFrequency {
+        file: String,
+        /// Name for `Frequency:`, the file name if not given
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Index a JLPT vocabulary list
+    ImportJlpt {
+        file: String,
+        /// Level of every word (N5 … N1), unless a column says
+        #[arg(short, long)]
+        level: Option<String>,
+        /// Name of the list, replaced when imported again; the file name if not given
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Indexed dictionaries and their sizes
+    List,
+}
+
+// One headword of a dictionary with its senses
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    pub forms: Vec<(String, bool)>,
+    pub readings: Vec<(String, bool)>,
+    pub senses: Vec<Sense>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sense {
+    pub pos: Vec<String>,
+    pub lang: String,
+    pub glosses: Vec<String>,
+}
+
+impl Entry {
+    #[inline]
+    pub fn is_common(&self) -> bool {
+        self.forms
+            .iter()
+            .chain(&self.readings)
+            .any(|(_, common)| *common)
+    }
+}
+
+// ke_pri / re_pri values JMdict counts as common
+const COMMON_PRIORITIES: [&str; 5] = ["news1", "ichi1", "spec1", "spec2", "gai1"];
+
+pub fn create_dictionary_sql() -> Vec<String> {
+    let entry = Table::create()
+        .table(Alias::new("dict_entry"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("dictionary")).string().not_null())
+        .col(ColumnDef::new(Alias::new("entry_id")).integer().not_null())
+        .col(ColumnDef::new(Alias::new("form")).string().not_null())
+        .col(ColumnDef::new(Alias::new("reading")).string())
+        .col(
+            ColumnDef::new(Alias::new("common"))
+                .boolean()
+                .not_null()
+                .default(0),
+        )
+        .to_owned();
+
+    let sense = Table::create()
+        .table(Alias::new("dict_sense"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("dictionary")).string().not_null())
+        .col(ColumnDef::new(Alias::new("entry_id")).integer().not_null())
+        .col(ColumnDef::new(Alias::new("position")).integer().not_null())
+        .col(ColumnDef::new(Alias::new("lang")).string().not_null())
+        // JSON arrays
+        .col(ColumnDef::new(Alias::new("pos")).json().not_null())
+        .col(ColumnDef::new(Alias::new("gloss")).json().not_null())
+        .to_owned();
+
+    let form_index = Index::create()
+        .if_not_exists()
+        .name("dict_entry_form")
+        .table(Alias::new("dict_entry"))
+        .col(Alias::new("dictionary"))
+        .col(Alias::new("form"))
+        .to_owned();
+
+    let sense_index = Index::create()
+        .if_not_exists()
+        .name("dict_sense_entry")
+        .table(Alias::new("dict_sense"))
+        .col(Alias::new("dictionary"))
+        .col(Alias::new("entry_id"))
+        .to_owned();
+
+    vec![
+        entry.build(SqliteQueryBuilder),
+        sense.build(SqliteQueryBuilder),
+        form_index.build(SqliteQueryBuilder),
+        sense_index.build(SqliteQueryBuilder),
+        kanjidic::create_kanji_sql(),
+    ]
+    .into_iter()
+    .chain(examples::create_example_sql())
+    .chain(pitch::create_pitch_sql())
+    .chain(frequency::create_frequency_sql())
+    .collect()
+}
+
+pub fn dict(conn: &mut Connection, action: DictAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        DictAction::ImportJmdict { file, lang } => {
+            let count = if file.ends_with(".json") {
+                let entries = read_jmdict_json(&file)?;
+                store_entries(conn, "jmdict", entries.into_iter().map(Ok), &lang)?
+            } else {
+                store_entries(conn, "jmdict", read_jmdict_xml(&file)?, &lang)?
+            };
+            println!("✅ Indexed {} JMdict entries from '{}'", count, file);
+        }
+        DictAction::ImportYomitan { file, name } => {
+            let (title, entries) = yomitan::read_zip(&file)?;
+            let name = name.unwrap_or(title);
+            let count = store_entries(conn, &name, entries.into_iter().map(Ok), &[])?;
+            println!("✅ Indexed {} entries from '{}' as '{}'", count, file, name);
+        }
+        DictAction::ImportKanjidic { file, lang } => {
+            let characters = kanjidic::read_xml(&file, &lang)?;
+            let count = kanjidic::store(conn, &characters)?;
+            println!("✅ Indexed {} kanji from '{}'", count, file);
+        }
+        DictAction::ImportPitch { file } => {
+            let rows = pitch::read_tsv(&file)?;
+            let count = pitch::store(conn, &rows)?;
+            println!("✅ Indexed {} pitch accents from '{}'", count, file);
+        }
+        DictAction::ImportTatoeba { file } => {
+            let pairs = examples::read_tsv(&file)?;
+            let count = examples::store(conn, &pairs)?;
+            println!("✅ Indexed {} sentences from '{}'", count, file);
+        }
+        DictAction::ImportFrequency { file, name } => {
+            let name = name.unwrap_or_else(|| list_name(&file));
+            let words = frequency::read_frequency(&file)?;
+            let count = frequency::store_frequency(conn, &name, &words)?;
+            println!("✅ Indexed {} words from '{}' as '{}'", count, file, name);
+        }
+        DictAction::ImportJlpt { file, level, name } => {
+            let name = name.unwrap_or_else(|| list_name(&file));
+            let words = frequency::read_jlpt(&file, level.as_deref())?;
+            let count = frequency::store_jlpt(conn, &name, &words)?;
+            println!(
+                "✅ Indexed {} JLPT words from '{}' as '{}'",
+                count, file, name
+            );
+        }
+        DictAction::List => {
+            let (sql, values) = Query::select()
+                .column(Alias::new("dictionary"))
+                .expr(Func::count_distinct(Expr::col(Alias::new("entry_id"))))
+                .from(Alias::new("dict_entry"))
+                .group_by_col(Alias::new("dictionary"))
+                .order_by(Alias::new("dictionary"), Order::Asc)
+                .to_owned()
+                .build_rusqlite(SqliteQueryBuilder);
+
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt
+                .query_map(&*values.as_params(), |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            let kanji = kanjidic::count(conn)?;
+
+            let (sql, values) = Query::select()
+                .expr(Func::count(Expr::col(Alias::new("expression"))))
+                .from(Alias::new("dict_pitch"))
+                .to_owned()
+                .build_rusqlite(SqliteQueryBuilder);
+            let accents: i64 = conn.query_row(&sql, &*values.as_params(), |row| row.get(0))?;
+
+            let (sql, values) = Query::select()
+                .expr(Func::count(Expr::col(Alias::new("sentence"))))
+                .from(Alias::new("dict_example"))
+                .to_owned()
+                .build_rusqlite(SqliteQueryBuilder);
+            let sentences: i64 = conn.query_row(&sql, &*values.as_params(), |row| row.get(0))?;
+
+            let (lists, jlpt) = frequency::lists(conn)?;
+
+            if rows.is_empty()
+                && kanji == 0
+                && accents == 0
+                && sentences == 0
+                && lists.is_empty()
+                && jlpt == 0
+            {
+                println!("No dictionaries indexed.");
+            }
+            for (name, count) in rows {
+                println!("  {:<24} {} entries", name, count);
+            }
+            if kanji > 0 {
+                println!("  {:<24} {} kanji", "KANJIDIC2", kanji);
+            }
+            if accents > 0 {
+                println!("  {:<24} {} words", "Pitch accent", accents);
+            }
+            if sentences > 0 {
+                println!("  {:<24} {} sentences", "Tatoeba", sentences);
+            }
+            for (name, count) in lists {
+                println!("  {:<24} {} ranked words", name, count);
+            }
+            if jlpt > 0 {
+                println!("  {:<24} {} words", "JLPT", jlpt);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// A list's name from its file: `n5.csv` → `n5`
+fn list_name(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| file.to_string())
+}
+
+// Replace a dictionary's index; senses in other languages are dropped (none if empty).
+// Entries are stored as they are read, the first read error rolls everything back
+pub fn store_entries(
+    conn: &mut Connection,
+    dictionary: &str,
+    entries: impl IntoIterator<Item = Result<(i64, Entry), Box<dyn std::error::Error>>>,
+    langs: &[String],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    for table in ["dict_entry", "dict_sense"] {
+        let (sql, values) = Query::delete()
+            .from_table(Alias::new(table))
+            .and_where(Expr::col(Alias::new("dictionary")).eq(dictionary))
+            .to_owned()
+            .build_rusqlite(SqliteQueryBuilder);
+        tx.execute(&sql, &*values.as_params())?;
+    }
+
+    // Built once and reused, a JMdict has a few hundred thousand rows
+    let (insert_entry, _) = Query::insert()
+        .into_table(Alias::new("dict_entry"))
+        .columns(["dictionary", "entry_id", "form", "reading", "common"].map(Alias::new))
+        .values_panic(["", "", "", "", ""].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let (insert_sense, _) = Query::insert()
+        .into_table(Alias::new("dict_sense"))
+        .columns(["dictionary", "entry_id", "position", "lang", "pos", "gloss"].map(Alias::new))
+        .values_panic(["", "", "", "", "", ""].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    let mut count = 0;
+    {
+        let mut entry_stmt = tx.prepare_cached(&insert_entry)?;
+        let mut sense_stmt = tx.prepare_cached(&insert_sense)?;
+
+        for entry in entries {
+            let (id, entry) = entry?;
+            let reading = entry.readings.first().map(|(reading, _)| reading.as_str());
+            for (form, common) in &entry.forms {
+                entry_stmt.execute(params![dictionary, id, form, reading, common])?;
+            }
+            for (form, common) in &entry.readings {
+                entry_stmt.execute(params![dictionary, id, form, form, common])?;
+            }
+
+            let senses = entry
+                .senses
+                .iter()
+                .filter(|sense| langs.is_empty() || langs.contains(&sense.lang));
+            for (position, sense) in senses.enumerate() {
+                sense_stmt.execute(params![
+                    dictionary,
+                    id,
+                    position as i64,
+                    sense.lang,
+                    serde_json::to_string(&sense.pos)?,
+                    serde_json::to_string(&sense.glosses)?,
+                ])?;
+            }
+            count += 1;
+        }
+    }
+
+    tx.commit()?;
+    Ok(count)
+}
+
+// Streamed one entry at a time, the full JMdict is too big to hold in memory
+fn read_jmdict_xml(
+    filename: &str,
+) -> Result<
+    impl Iterator<Item = Result<(i64, Entry), Box<dyn std::error::Error>>>,
+    Box<dyn std::error::Error>,
+> {
+    let mut reader = Reader::from_reader(BufReader::new(std::fs::File::open(filename)?));
+    reader.config_mut().trim_text(true);
+
+    let mut buf = Vec::new();
+    let mut tag = String::new();
+    let mut id = 0;
+    let mut entry = Entry::default();
+    let mut sense = Sense::default();
+    let mut last_pos: Vec<String> = Vec::new();
+
+    // The next entry, None at the end of the file
+    let mut next = move || -> Result<Option<(i64, Entry)>, Box<dyn std::error::Error>> {
+        loop {
+            buf.clear();
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(e) => {
+                    tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                    if tag == "gloss" {
+                        sense.lang = e
+                            .try_get_attribute("xml:lang")?
+                            .map(|attr| String::from_utf8_lossy(&attr.value).into_owned())
+                            .unwrap_or_else(|| "eng".to_string());
+                    }
+                }
+                Event::Text(e) => {
+                    // POS and the like are DTD entities (&n;, &v1;), kept as their code
+                    let raw = String::from_utf8_lossy(&e).into_owned();
+                    let code = || {
+                        raw.trim_start_matches('&')
+                            .trim_end_matches(';')
+                            .to_string()
+                    };
+                    match tag.as_str() {
+                        "ent_seq" => id = raw.trim().parse()?,
+                        "keb" => entry.forms.push((raw, false)),
+                        "reb" => entry.readings.push((raw, false)),
+                        "ke_pri" if COMMON_PRIORITIES.contains(&raw.as_str()) => {
+                            if let Some(form) = entry.forms.last_mut() {
+                                form.1 = true;
+                            }
+                        }
+                        "re_pri" if COMMON_PRIORITIES.contains(&raw.as_str()) => {
+                            if let Some(reading) = entry.readings.last_mut() {
+                                reading.1 = true;
+                            }
+                        }
+                        "pos" => sense.pos.push(code()),
+                        "gloss" => sense.glosses.push(e.unescape()?.into_owned()),
+                        _ => {}
+                    }
+                }
+                Event::End(e) => {
+                    tag.clear();
+                    match e.name().as_ref() {
+                        b"sense" => {
+                            // A sense without POS has the one before it
+                            if sense.pos.is_empty() {
+                                sense.pos = last_pos.clone();
+                            } else {
+                                last_pos = sense.pos.clone();
+                            }
+                            if sense.lang.is_empty() {
+                                sense.lang = "eng".to_string();
+                            }
+                            entry.senses.push(std::mem::take(&mut sense));
+                        }
+                        b"entry" => {
+                            last_pos.clear();
+                            return Ok(Some((id, std::mem::take(&mut entry))));
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+    };
+
+    Ok(std::iter::from_fn(move || next().transpose()))
+}
+
+// jmdict-simplified (github.com/scriptin/jmdict-simplified)
+#[derive(Deserialize)]
+struct SimplifiedJmdict {
+    words: Vec<SimplifiedWord>,
+}
+
+#[derive(Deserialize)]
+struct SimplifiedWord {
+    id: String,
+    kanji: Vec<SimplifiedForm>,
+    kana: Vec<SimplifiedForm>,
+    sense: Vec<SimplifiedSense>,
+}
+
+#[derive(Deserialize)]
+struct SimplifiedForm {
+    common: bool,
+    text: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimplifiedSense {
+    part_of_speech: Vec<String>,
+    gloss: Vec<SimplifiedGloss>,
+}
+
+#[derive(Deserialize)]
+struct SimplifiedGloss {
+    lang: String,
+    text: String,
+}
+
+fn read_jmdict_json(filename: &str) -> Result<Vec<(i64, Entry)>, Box<dyn std::error::Error>> {
+    let file = BufReader::new(std::fs::File::open(filename)?);
+    let jmdict: SimplifiedJmdict = serde_json::from_reader(file)?;
+
+    jmdict
+        .words
+        .into_iter()
+        .map(|word| {
+            let id = word.id.parse()?;
+            let mut senses = Vec::new();
+            for sense in word.sense {
+                // One sense per gloss language, like the XML
+                let mut by_lang: Vec<(String, Vec<String>)> = Vec::new();
+                for gloss in sense.gloss {
+                    match by_lang.iter_mut().find(|(lang, _)| *lang == gloss.lang) {
+                        Some((_, glosses)) => glosses.push(gloss.text),
+                        None => by_lang.push((gloss.lang, vec![gloss.text])),
+                    }
+                }
+                senses.extend(by_lang.into_iter().map(|(lang, glosses)| Sense {
+                    pos: sense.part_of_speech.clone(),
+                    lang,
+                    glosses,
+                }));
+            }
+
+            Ok((
+                id,
+                Entry {
+                    forms: word.kanji.into_iter().map(|f| (f.text, f.common)).collect(),
+                    readings: word.kana.into_iter().map(|f| (f.text, f.common)).collect(),
+                    senses,
+                },
+            ))
+        })
+        .collect()
+}
+
+// Best entry for a word: common ones first, then dictionary order
+pub fn lookup(
+    conn: &Connection,
+    dictionary: &str,
+    word: &str,
+    lang: &str,
+) -> rusqlite::Result<Option<Entry>> {
+    let (sql, values) = Query::select()
+        .column(Alias::new("entry_id"))
+        .from(Alias::new("dict_entry"))
+        .and_where(Expr::col(Alias::new("dictionary")).eq(dictionary))
+        .and_where(Expr::col(Alias::new("form")).eq(word))
+        .order_by(Alias::new("common"), Order::Desc)
+        .order_by(Alias::new("entry_id"), Order::Asc)
+        .limit(1)
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let Some(id) = conn
+        .query_row(&sql, &*values.as_params(), |row| row.get::<_, i64>(0))
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let (sql, values) = Query::select()
+        .columns(["form", "common"].map(Alias::new))
+        .from(Alias::new("dict_entry"))
+        .and_where(Expr::col(Alias::new("dictionary")).eq(dictionary))
+        .and_where(Expr::col(Alias::new("entry_id")).eq(id))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let mut stmt = conn.prepare(&sql)?;
+    let forms = stmt
+        .query_map(&*values.as_params(), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(String, bool)>>>()?;
+
+    let (sql, values) = Query::select()
+        .columns(["pos", "gloss"].map(Alias::new))
+        .from(Alias::new("dict_sense"))
+        .and_where(Expr::col(Alias::new("dictionary")).eq(dictionary))
+        .and_where(Expr::col(Alias::new("entry_id")).eq(id))
+        // Senses of an unknown language (Yomitan) match any
+        .cond_where(
+            Cond::any()
+                .add(Expr::col(Alias::new("lang")).eq(lang))
+                .add(Expr::col(Alias::new("lang")).eq("")),
+        )
+        .order_by(Alias::new("position"), Order::Asc)
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let mut stmt = conn.prepare(&sql)?;
+    let senses = stmt
+        .query_map(&*values.as_params(), |row| {
+            let pos: String = row.get(0)?;
+            let gloss: String = row.get(1)?;
+            Ok(Sense {
+                pos: serde_json::from_str(&pos).unwrap_or_default(),
+                lang: lang.to_string(),
+                glosses: serde_json::from_str(&gloss).unwrap_or_default(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<Sense>>>()?;
+
+    Ok(Some(Entry {
+        forms,
+        readings: Vec::new(),
+        senses,
+    }))
+}
+
+// The part of an entry a field takes, as an input value to be typed
+pub fn entry_value(field: &FieldSpec, entry: &Entry) -> Option<JsonValue> {
+    match field.metadata.entry.unwrap_or_default() {
+        DictionaryEntry::Gloss => {
+            let senses: Vec<String> = entry
+                .senses
+                .iter()
+                .filter(|sense| !sense.glosses.is_empty())
+                .map(|sense| sense.glosses.join("; "))
+                .collect();
+            match senses.len() {
+                0 => None,
+                1 => Some(senses[0].clone().into()),
+                // Numbered, one sense per line
+                _ => Some(
+                    senses
+                        .iter()
+                        .enumerate()
+                        .map(|(i, sense)| format!("{}. {}", i + 1, sense))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .into(),
+                ),
+            }
+        }
+        DictionaryEntry::Pos => {
+            let mut pos: Vec<&String> = Vec::new();
+            for code in entry.senses.iter().flat_map(|sense| &sense.pos) {
+                if !pos.contains(&code) {
+                    pos.push(code);
+                }
+            }
+            (!pos.is_empty()).then(|| pos.into_iter().cloned().collect::<Vec<_>>().into())
+        }
+        DictionaryEntry::Common => Some(entry.is_common().into()),
+    }
+}
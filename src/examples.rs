@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+
+use lindera::tokenizer::Tokenizer;
+use rusqlite::{Connection, params};
+use sea_query::{Alias, ColumnDef, Expr, ExprTrait, Func, Index, Query, SqliteQueryBuilder, Table};
+use sea_query_rusqlite::RusqliteBinder;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    frequency, kanjidic, mine,
+    models::{CardMetadata, FieldSpec, FieldType},
+    row_to_raw_hashmap,
+    validate::{self, is_kanji},
+    value::FieldValue,
+};
+
+pub fn create_example_sql() -> Vec<String> {
+    let example = Table::create()
+        .table(Alias::new("dict_example"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("sentence")).text().not_null())
+        .col(ColumnDef::new(Alias::new("translation")).text().not_null())
+        .to_owned();
+
+    // Dictionary forms of each sentence's words, so a word's sentences are an index lookup
+    let word = Table::create()
+        .table(Alias::new("dict_example_word"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("word")).string().not_null())
+        // dict_example.rowid
+        .col(
+            ColumnDef::new(Alias::new("example_id"))
+                .integer()
+                .not_null(),
+        )
+        .to_owned();
+
+    let index = Index::create()
+        .if_not_exists()
+        .name("dict_example_word_word")
+        .table(Alias::new("dict_example_word"))
+        .col(Alias::new("word"))
+        .to_owned();
+
+    vec![
+        example.build(SqliteQueryBuilder),
+        word.build(SqliteQueryBuilder),
+        index.build(SqliteQueryBuilder),
+    ]
+}
+
+// Tatoeba sentence pairs (id, sentence, id, translation) or plain (sentence, translation)
+pub fn read_tsv(filename: &str) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let mut seen = HashSet::new();
+    let mut pairs = Vec::new();
+    for line in content.lines() {
+        let columns: Vec<&str> = line.split('\t').collect();
+        let (sentence, translation) = match columns.as_slice() {
+            [_, sentence, _, translation, ..] => (sentence, translation),
+            [sentence, translation] => (sentence, translation),
+            _ => continue,
+        };
+
+        // A sentence has one row per translation, the first one is kept
+        if sentence.is_empty() || !seen.insert(sentence.to_string()) {
+            continue;
+        }
+        pairs.push((sentence.to_string(), translation.to_string()));
+    }
+
+    Ok(pairs)
+}
+
+// Replaces the stored sentences
+pub fn store(
+    conn: &mut Connection,
+    pairs: &[(String, String)],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tokenizer = mine::tokenizer()?;
+    let tx = conn.transaction()?;
+
+    for table in ["dict_example", "dict_example_word"] {
+        let (sql, values) = Query::delete()
+            .from_table(Alias::new(table))
+            .to_owned()
+            .build_rusqlite(SqliteQueryBuilder);
+        tx.execute(&sql, &*values.as_params())?;
+    }
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("dict_example"))
+        .columns(["sentence", "translation"].map(Alias::new))
+        .values_panic([(); 2].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let (insert_word, _) = Query::insert()
+        .into_table(Alias::new("dict_example_word"))
+        .columns(["word", "example_id"].map(Alias::new))
+        .values_panic([(); 2].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        let mut word_stmt = tx.prepare_cached(&insert_word)?;
+        for (sentence, translation) in pairs {
+            stmt.execute(params![sentence, translation])?;
+            let id = tx.last_insert_rowid();
+            for word in mine::lemmas(&tokenizer, sentence)? {
+                word_stmt.execute(params![word, id])?;
+            }
+        }
+    }
+
+    tx.commit()?;
+    Ok(pairs.len())
+}
+
+// `%` and `_` of the text match themselves, with `ESCAPE '\'`
+fn like_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// Sentences with one of the word's dictionary forms (the longest, the rarest most likely)
+// that also contain its stem
+fn candidates(
+    conn: &Connection,
+    lemmas: &[String],
+    word: &str,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let Some(lemma) = lemmas.iter().max_by_key(|lemma| lemma.chars().count()) else {
+        return Ok(Vec::new());
+    };
+
+    let ids = Query::select()
+        .column(Alias::new("example_id"))
+        .from(Alias::new("dict_example_word"))
+        .and_where(Expr::col(Alias::new("word")).eq(lemma.as_str()))
+        .to_owned();
+    let (sql, values) = Query::select()
+        .columns(["sentence", "translation"].map(Alias::new))
+        .from(Alias::new("dict_example"))
+        .and_where(Expr::col(Alias::new("rowid")).in_subquery(ids))
+        .and_where(Expr::cust_with_values(
+            "sentence LIKE ? ESCAPE '\\'",
+            [format!("%{}%", like_escape(validate::stem(word)))],
+        ))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_map(&*values.as_params(), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+// Words ranked past this in the frequency lists count as rare
+const COMMON_RANK: i64 = 5000;
+
+// What makes a sentence easy to read
+struct Scorer<'a> {
+    conn: &'a Connection,
+    tokenizer: Tokenizer,
+    // Kanji of the known words, empty without a list
+    known: HashSet<char>,
+    // Frequency lists to tell rare words apart, best first; kanji grades without them
+    lists: Vec<String>,
+    // Whether KANJIDIC2 is there to tell rare kanji apart
+    indexed: bool,
+    // Jōyō or not, per kanji seen so far
+    common: HashMap<char, bool>,
+    // Common or not, per word seen so far
+    common_words: HashMap<String, bool>,
+}
+
+impl Scorer<'_> {
+    // Lower is better: length, plus a penalty per rare word (or rare kanji) and unknown kanji
+    fn cost(&mut self, sentence: &str, word: &str) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut cost = sentence.chars().count();
+        if !self.lists.is_empty() {
+            for other in mine::lemmas(&self.tokenizer, sentence)? {
+                if other != word && !self.is_common_word(&other)? {
+                    cost += 10;
+                }
+            }
+        }
+        for c in sentence
+            .chars()
+            .filter(|c| is_kanji(*c) && !word.contains(*c))
+        {
+            if self.lists.is_empty() && !self.is_common(c) {
+                cost += 10;
+            }
+            if !self.known.is_empty() && !self.known.contains(&c) {
+                cost += 5;
+            }
+        }
+        Ok(cost)
+    }
+
+    // Ranked within COMMON_RANK by the first list that has it
+    fn is_common_word(&mut self, word: &str) -> rusqlite::Result<bool> {
+        if let Some(common) = self.common_words.get(word) {
+            return Ok(*common);
+        }
+        let mut common = false;
+        for list in &self.lists {
+            if let Some(rank) = frequency::rank(self.conn, list, word)? {
+                common = rank <= COMMON_RANK;
+                break;
+            }
+        }
+        self.common_words.insert(word.to_string(), common);
+        Ok(common)
+    }
+
+    // Grades 1-8 are the jōyō kanji; everything counts as common without KANJIDIC2
+    fn is_common(&mut self, c: char) -> bool {
+        if !self.indexed {
+            return true;
+        }
+        let conn = self.conn;
+        *self
+            .common
+            .entry(c)
+            .or_insert_with(|| match kanjidic::lookup(conn, c) {
+                Ok(Some(kanji)) => kanji.grade.is_some_and(|grade| grade <= 8),
+                Ok(None) => false,
+                Err(_) => true,
+            })
+    }
+
+    fn pick(&mut self, word: &str) -> Result<Option<(String, String)>, Box<dyn std::error::Error>> {
+        let lemmas = mine::lemmas(&self.tokenizer, word)?;
+        let mut best: Option<(usize, (String, String))> = None;
+        for (sentence, translation) in candidates(self.conn, &lemmas, word)? {
+            if !validate::contains_word(&sentence, word) {
+                continue;
+            }
+            let cost = self.cost(&sentence, word)?;
+            if best.as_ref().is_none_or(|(best, _)| cost < *best) {
+                best = Some((cost, (sentence, translation)));
+            }
+        }
+        Ok(best.map(|(_, pair)| pair))
+    }
+}
+
+// Fill empty example sentences (and their translations) of stored cards from the index
+pub fn examples(
+    conn: &mut Connection,
+    card_metadata: &CardMetadata,
+    known: Option<&str>,
+    tag: &str,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(sentence_field) = card_metadata
+        .fields
+        .iter()
+        .find(|field| field.metadata.example_for.is_some())
+    else {
+        return Err("no field has `Example For`".into());
+    };
+    let source = sentence_field
+        .metadata
+        .example_for
+        .clone()
+        .unwrap_or_default();
+    let translation_field = card_metadata.fields.iter().find(|field| {
+        field.metadata.translation_of.as_deref() == Some(sentence_field.name.as_str())
+    });
+    let tags_field = card_metadata.fields.iter().find(|field| field.is_tags());
+    if tags_field.is_none() {
+        eprintln!("⚠️ No `Tags` field, filled cards aren't tagged '{}'", tag);
+    }
+    let key = card_metadata.get_main_key();
+
+    let known = match known {
+        Some(file) => std::fs::read_to_string(file)?
+            .lines()
+            // First column, so a frequency list or an Anki export works too
+            .filter_map(|line| line.split(['\t', ',']).next())
+            .flat_map(str::chars)
+            .filter(|c| is_kanji(*c))
+            .collect(),
+        None => HashSet::new(),
+    };
+
+    let (sql, values) = card_metadata.get_all_cards_sql();
+    let cards = {
+        let mut stmt = conn.prepare(&sql)?;
+        stmt.query_map(&*values.as_params(), row_to_raw_hashmap)?
+            .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?
+    };
+
+    // The template's lists, or every stored one
+    let lists = match card_metadata.get_frequency_field() {
+        Some(field) => field
+            .frequency_lists()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        None => frequency::lists(conn)?
+            .0
+            .into_iter()
+            .map(|(list, _)| list)
+            .collect(),
+    };
+
+    let tx = conn.transaction()?;
+    if count(&tx, "dict_example")? > 0 && count(&tx, "dict_example_word")? == 0 {
+        return Err("the example index has no words yet, run `dict import-tatoeba` again".into());
+    }
+    let mut scorer = Scorer {
+        conn: &tx,
+        tokenizer: mine::tokenizer()?,
+        known,
+        lists,
+        indexed: kanjidic::count(&tx)? > 0,
+        common: HashMap::new(),
+        common_words: HashMap::new(),
+    };
+
+    let mut filled = 0;
+    for card in &cards {
+        let empty = |field: &FieldSpec| card.get(&field.name).is_none_or(|value| value.is_empty());
+        if !empty(sentence_field) {
+            continue;
+        }
+        let Some(word) = card.get(&source).filter(|word| !word.is_empty()) else {
+            continue;
+        };
+        let Some((sentence, translation)) = scorer.pick(word)? else {
+            continue;
+        };
+
+        println!("{}: {} ({})", word, sentence, translation);
+        if dry_run {
+            continue;
+        }
+
+        let mut fields = vec![key.get_alias(), sentence_field.get_alias()];
+        let mut values = vec![
+            FieldValue::parse(key, &card[&key.name])?,
+            FieldValue::Text(sentence),
+        ];
+        if let Some(field) = translation_field.filter(|field| empty(field)) {
+            fields.push(field.get_alias());
+            values.push(FieldValue::Text(translation));
+        }
+        if let Some(field) = tags_field {
+            let stored = card.get(&field.name).cloned().unwrap_or_default();
+            fields.push(field.get_alias());
+            values.push(FieldValue::from_input(
+                field,
+                &add_tag(field, &stored, tag),
+            )?);
+        }
+
+        let Some((fields, values)) = card_metadata.preprocess_data(&tx, &fields, &values) else {
+            continue;
+        };
+
+        // Only what changed and what is derived from it
+        let changed: Vec<&str> = [Some(sentence_field), translation_field, tags_field]
+            .into_iter()
+            .flatten()
+            .map(|field| field.name.as_str())
+            .collect();
+        let (fields, values) = card_metadata.only_changed(&changed, fields, values);
+
+        let (sql, params) = card_metadata.insert_or_update_card_with_fields_sql(
+            &key.get_alias(),
+            &fields,
+            &values,
+            &HashMap::new(),
+        );
+        tx.execute(sql.as_str(), &*params.as_params())?;
+        filled += 1;
+    }
+    drop(scorer);
+    tx.commit()?;
+
+    match dry_run {
+        true => println!("Dry run, nothing saved."),
+        false => println!("✅ Filled examples of {} cards", filled),
+    }
+
+    Ok(())
+}
+
+fn count(conn: &Connection, table: &str) -> rusqlite::Result<i64> {
+    let (sql, values) = Query::select()
+        .expr(Func::count(Expr::col(Alias::new("rowid"))))
+        .from(Alias::new(table))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    conn.query_row(&sql, &*values.as_params(), |row| row.get(0))
+}
+
+// The stored tags with `tag` added once
+pub fn add_tag(field: &FieldSpec, stored: &str, tag: &str) -> JsonValue {
+    match field.field_type {
+        FieldType::List => {
+            let mut tags: Vec<JsonValue> = serde_json::from_str(stored).unwrap_or_default();
+            if !tags.iter().any(|item| item.as_str() == Some(tag)) {
+                tags.push(tag.into());
+            }
+            JsonValue::Array(tags)
+        }
+        // Space separated, like Anki
+        _ => {
+            let mut tags: Vec<&str> = stored.split_whitespace().collect();
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            tags.join(" ").into()
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use rusqlite::{Connection, OptionalExtension, params};
+use sea_query::{
+    Alias, ColumnDef, Expr, ExprTrait, Func, Index, Order, Query, SqliteQueryBuilder, Table,
+};
+use sea_query_rusqlite::RusqliteBinder;
+
+pub fn create_frequency_sql() -> Vec<String> {
+    let frequency = Table::create()
+        .table(Alias::new("dict_frequency"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("list")).string().not_null())
+        .col(ColumnDef::new(Alias::new("word")).string().not_null())
+        // 1 is the most frequent
+        .col(ColumnDef::new(Alias::new("rank")).integer().not_null())
+        .to_owned();
+
+    let index = Index::create()
+        .if_not_exists()
+        .name("dict_frequency_word")
+        .table(Alias::new("dict_frequency"))
+        .col(Alias::new("list"))
+        .col(Alias::new("word"))
+        .to_owned();
+
+    vec![
+        frequency.build(SqliteQueryBuilder),
+        index.build(SqliteQueryBuilder),
+    ]
+    .into_iter()
+    .chain(create_jlpt_sql())
+    .collect()
+}
+
+fn create_jlpt_sql() -> Vec<String> {
+    let jlpt = Table::create()
+        .table(Alias::new("dict_jlpt"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("list")).string().not_null())
+        .col(ColumnDef::new(Alias::new("word")).string().not_null())
+        // 5 for N5 … 1 for N1
+        .col(ColumnDef::new(Alias::new("level")).integer().not_null())
+        .to_owned();
+
+    let index = Index::create()
+        .if_not_exists()
+        .name("dict_jlpt_word")
+        .table(Alias::new("dict_jlpt"))
+        .col(Alias::new("word"))
+        .to_owned();
+
+    vec![
+        jlpt.build(SqliteQueryBuilder),
+        index.build(SqliteQueryBuilder),
+    ]
+}
+
+// JLPT words stored before lists were kept apart go under a `jlpt` list
+pub fn upgrade_jlpt_sql(existing: &[String]) -> Vec<String> {
+    if existing.is_empty() || existing.iter().any(|col| col == "list") {
+        return Vec::new();
+    }
+
+    let old = Alias::new("dict_jlpt_old");
+    let mut sqls = vec![
+        Table::rename()
+            .table(Alias::new("dict_jlpt"), old.clone())
+            .to_owned()
+            .build(SqliteQueryBuilder),
+    ];
+    sqls.extend(create_jlpt_sql());
+    sqls.push(
+        "INSERT INTO dict_jlpt (list, word, level) SELECT 'jlpt', word, level FROM dict_jlpt_old"
+            .to_string(),
+    );
+    sqls.push(
+        Table::drop()
+            .table(old)
+            .to_owned()
+            .build(SqliteQueryBuilder),
+    );
+    sqls
+}
+
+// The word is the first column that isn't a number. A numeric first column is the rank
+// (`rank, word, count`, ties allowed); otherwise the list is sorted most frequent first
+// and the rank is the position of the word's first line
+pub fn read_frequency(filename: &str) -> Result<Vec<(String, i64)>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let mut ranks: HashMap<&str, i64> = HashMap::new();
+    let mut words = Vec::new();
+    for line in content.lines() {
+        let columns: Vec<&str> = line.split(['\t', ',']).map(str::trim).collect();
+        let Some(&word) = columns
+            .iter()
+            .find(|column| !column.is_empty() && column.parse::<f64>().is_err())
+        else {
+            continue;
+        };
+        if word.starts_with('#') || ranks.contains_key(word) {
+            continue;
+        }
+
+        let rank = columns
+            .first()
+            .and_then(|column| column.parse::<i64>().ok())
+            .unwrap_or(words.len() as i64 + 1);
+        ranks.insert(word, rank);
+        words.push((word.to_string(), rank));
+    }
+
+    Ok(words)
+}
+
+// Replaces the list
+pub fn store_frequency(
+    conn: &mut Connection,
+    list: &str,
+    words: &[(String, i64)],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    let (sql, values) = Query::delete()
+        .from_table(Alias::new("dict_frequency"))
+        .and_where(Expr::col(Alias::new("list")).eq(list))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    tx.execute(&sql, &*values.as_params())?;
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("dict_frequency"))
+        .columns(["list", "word", "rank"].map(Alias::new))
+        .values_panic([(); 3].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        for (word, rank) in words {
+            stmt.execute(params![list, word, rank])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(words.len())
+}
+
+// `word` with `level` (N5 … N1) for every line, unless a column names the level
+pub fn read_jlpt(
+    filename: &str,
+    level: Option<&str>,
+) -> Result<Vec<(String, i64)>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+    let default = level.map(parse_level).transpose()?;
+
+    let mut words = Vec::new();
+    for line in content.lines() {
+        let columns: Vec<&str> = line.split(['\t', ',']).map(str::trim).collect();
+        let Some(word) = columns.first().filter(|word| !word.is_empty()) else {
+            continue;
+        };
+
+        let level = columns[1..]
+            .iter()
+            .find_map(|column| parse_level(column).ok())
+            .or(default);
+        match level {
+            Some(level) => words.push((word.to_string(), level)),
+            // A header line
+            None if words.is_empty() => {}
+            None => {
+                return Err(format!("{filename}: no JLPT level for '{word}' (use --level)").into());
+            }
+        }
+    }
+
+    Ok(words)
+}
+
+fn parse_level(text: &str) -> Result<i64, String> {
+    match text.trim().to_ascii_uppercase().strip_prefix('N') {
+        Some(level @ ("1" | "2" | "3" | "4" | "5")) => Ok(level.parse().unwrap_or_default()),
+        _ => Err(format!("expected a JLPT level N5 … N1, got '{text}'")),
+    }
+}
+
+// Replaces the list; a word on several lists keeps the easiest level (see `jlpt`)
+pub fn store_jlpt(
+    conn: &mut Connection,
+    list: &str,
+    words: &[(String, i64)],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    let (sql, values) = Query::delete()
+        .from_table(Alias::new("dict_jlpt"))
+        .and_where(Expr::col(Alias::new("list")).eq(list))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    tx.execute(&sql, &*values.as_params())?;
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("dict_jlpt"))
+        .columns(["list", "word", "level"].map(Alias::new))
+        .values_panic([(); 3].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        for (word, level) in words {
+            stmt.execute(params![list, word, level])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(words.len())
+}
+
+pub fn rank(conn: &Connection, list: &str, word: &str) -> rusqlite::Result<Option<i64>> {
+    let (sql, values) = Query::select()
+        .column(Alias::new("rank"))
+        .from(Alias::new("dict_frequency"))
+        .and_where(Expr::col(Alias::new("list")).eq(list))
+        .and_where(Expr::col(Alias::new("word")).eq(word))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    conn.query_row(&sql, &*values.as_params(), |row| row.get(0))
+        .optional()
+}
+
+// 5 for N5 … 1 for N1, the easiest of the lists with the word
+pub fn jlpt(conn: &Connection, word: &str) -> rusqlite::Result<Option<i64>> {
+    let (sql, values) = Query::select()
+        .expr(Func::max(Expr::col(Alias::new("level"))))
+        .from(Alias::new("dict_jlpt"))
+        .and_where(Expr::col(Alias::new("word")).eq(word))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    conn.query_row(&sql, &*values.as_params(), |row| row.get(0))
+}
+
+// Stored lists and their sizes, plus the JLPT words
+pub fn lists(conn: &Connection) -> rusqlite::Result<(Vec<(String, i64)>, i64)> {
+    let (sql, values) = Query::select()
+        .column(Alias::new("list"))
+        .expr(Func::count(Expr::col(Alias::new("word"))))
+        .from(Alias::new("dict_frequency"))
+        .group_by_col(Alias::new("list"))
+        .order_by(Alias::new("list"), Order::Asc)
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let mut stmt = conn.prepare(&sql)?;
+    let lists = stmt
+        .query_map(&*values.as_params(), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let (sql, values) = Query::select()
+        .expr(Func::count_distinct(Expr::col(Alias::new("word"))))
+        .from(Alias::new("dict_jlpt"))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let jlpt = conn.query_row(&sql, &*values.as_params(), |row| row.get(0))?;
+
+    Ok((lists, jlpt))
+}
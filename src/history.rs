@@ -0,0 +1,169 @@
+use rusqlite::{OptionalExtension, Transaction};
+use sha2::{Digest, Sha256};
+
+use serde_json::Value as JsonValue;
+
+use crate::{
+    db::write_rows,
+    models::{CardMetadata, ExportFormat},
+};
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ExportsAction {
+    /// List past export batches
+    List,
+    /// Write a past batch file again, byte for byte
+    Regenerate {
+        id: i64,
+        #[arg(short, long)]
+        file: Option<String>,
+    },
+    /// Mark the cards of a batch as unexported and drop the batch
+    Rollback { id: i64 },
+}
+
+#[inline]
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+// Record a batch inside the export transaction, one row per exported card
+pub fn record_batch(
+    tx: &Transaction,
+    card_metadata: &CardMetadata,
+    target: &str,
+    file: &str,
+    format: ExportFormat,
+    header: Option<&[String]>,
+    keys: &[String],
+    rows: &[JsonValue],
+) -> Result<i64, Box<dyn std::error::Error>> {
+    let header = header.map(serde_json::to_string).transpose()?;
+    let (sql, values) =
+        card_metadata.insert_export_batch_sql(target, file, rows.len(), format, header.as_deref());
+    tx.execute(&sql, &*values.as_params())?;
+    let batch_id = tx.last_insert_rowid();
+
+    for (card_key, row) in keys.iter().zip(rows) {
+        // The exact row written, so the file can be rebuilt after template changes
+        let content = serde_json::to_string(row)?;
+
+        let (sql, values) = card_metadata.insert_export_card_sql(
+            batch_id,
+            card_key,
+            &content_hash(&content),
+            &content,
+        );
+        tx.execute(&sql, &*values.as_params())?;
+    }
+
+    Ok(batch_id)
+}
+
+pub fn exports(
+    conn: &mut rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    action: ExportsAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        ExportsAction::List => {
+            let (sql, values) = card_metadata.get_export_batches_sql();
+            let mut stmt = conn.prepare(&sql)?;
+            let batches = stmt
+                .query_map(&*values.as_params(), |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+
+            if batches.is_empty() {
+                println!("No exports yet.");
+            }
+
+            for (id, created_at, target, file, card_count) in batches {
+                println!("#{id}  {created_at}  {target}  {file}  ({card_count} cards)");
+            }
+        }
+        ExportsAction::Regenerate { id, file } => {
+            let (sql, values) = card_metadata.get_export_batch_sql(id);
+            let (original, target, format, header) = conn
+                .query_row(&sql, &*values.as_params(), |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                    ))
+                })
+                .optional()?
+                .ok_or_else(|| format!("export batch #{id} not found"))?;
+
+            let (format, header) = match format.as_deref().and_then(ExportFormat::from_extension) {
+                Some(format) => (
+                    format,
+                    header
+                        .map(|header| serde_json::from_str::<Vec<String>>(&header))
+                        .transpose()?,
+                ),
+                // Recorded before the batch kept them: the file extension tells the format,
+                // then the target; CSV otherwise
+                None => {
+                    let target = card_metadata.get_target(&target);
+                    let format = ExportFormat::from_path(&original)
+                        .or(target.map(|target| target.format))
+                        .unwrap_or_default();
+                    let header = target
+                        .and_then(|target| card_metadata.get_export_profile(target).ok())
+                        .and_then(|profile| profile.get_header());
+                    (format, header)
+                }
+            };
+
+            let (sql, values) = card_metadata.get_export_batch_contents_sql(id);
+            let mut stmt = conn.prepare(&sql)?;
+            let contents = stmt
+                .query_map(&*values.as_params(), |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?;
+
+            let rows = contents
+                .iter()
+                .map(|content| serde_json::from_str(content))
+                .collect::<Result<Vec<JsonValue>, _>>()?;
+
+            let file = file.unwrap_or(original);
+            write_rows(header.as_deref(), &rows, &file, format)?;
+
+            println!(
+                "✅ Regenerated batch #{} ({} cards) to '{}'",
+                id,
+                rows.len(),
+                file
+            );
+        }
+        ExportsAction::Rollback { id } => {
+            let (sql, values) = card_metadata.get_export_batch_sql(id);
+            let target = conn
+                .query_row(&sql, &*values.as_params(), |row| row.get::<_, String>(1))
+                .optional()?
+                .ok_or_else(|| format!("export batch #{id} not found"))?;
+
+            let key = card_metadata.get_main_key();
+            let tx = conn.transaction()?;
+            for (sql, values) in
+                card_metadata.rollback_export_batch_tx(&key.get_alias(), &target, id)
+            {
+                tx.execute(&sql, &*values.as_params())?;
+            }
+            tx.commit()?;
+
+            println!("✅ Rolled back batch #{id}, its cards will be exported to '{target}' again");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use serde_json::Value as JsonValue;
+
+use crate::value::Record;
+
+// Cards from a JSON array, or from one object per line
+pub fn read_json(filename: &str, lines: bool) -> Result<Vec<Record>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let entries: Vec<JsonValue> = if lines {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| format!("{filename}:{}: {e}", i + 1))
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        serde_json::from_str(&content)?
+    };
+
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| match entry {
+            // Typed per field on import, like a cards.yaml record
+            JsonValue::Object(object) => Ok(object.into_iter().collect()),
+            _ => Err(format!("{filename}: entry {} is not an object", i + 1).into()),
+        })
+        .collect()
+}
@@ -0,0 +1,228 @@
+use std::io::BufReader;
+
+use quick_xml::{Reader, events::Event};
+use rusqlite::{Connection, OptionalExtension, params};
+use sea_query::{Alias, ColumnDef, Expr, ExprTrait, Func, Query, SqliteQueryBuilder, Table};
+use sea_query_rusqlite::RusqliteBinder;
+
+use crate::{models::MarkupFormat, render::escape_html};
+
+// One KANJIDIC2 character
+#[derive(Debug, Clone, Default)]
+pub struct Kanji {
+    pub literal: String,
+    pub meanings: Vec<String>,
+    pub onyomi: Vec<String>,
+    pub kunyomi: Vec<String>,
+    pub strokes: Option<i64>,
+    pub grade: Option<i64>,
+    // Pre-2010 levels, 4 (easiest) to 1
+    pub jlpt: Option<i64>,
+}
+
+pub fn create_kanji_sql() -> String {
+    Table::create()
+        .table(Alias::new("dict_kanji"))
+        .if_not_exists()
+        .col(
+            ColumnDef::new(Alias::new("literal"))
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        // JSON arrays
+        .col(ColumnDef::new(Alias::new("meanings")).json().not_null())
+        .col(ColumnDef::new(Alias::new("onyomi")).json().not_null())
+        .col(ColumnDef::new(Alias::new("kunyomi")).json().not_null())
+        .col(ColumnDef::new(Alias::new("strokes")).integer())
+        .col(ColumnDef::new(Alias::new("grade")).integer())
+        .col(ColumnDef::new(Alias::new("jlpt")).integer())
+        .to_owned()
+        .build(SqliteQueryBuilder)
+}
+
+// Meanings in `lang` (ISO 639-1, KANJIDIC2 leaves English unmarked)
+pub fn read_xml(filename: &str, lang: &str) -> Result<Vec<Kanji>, Box<dyn std::error::Error>> {
+    let mut reader = Reader::from_reader(BufReader::new(std::fs::File::open(filename)?));
+    reader.config_mut().trim_text(true);
+
+    let mut characters = Vec::new();
+    let mut buf = Vec::new();
+    let mut tag = String::new();
+    let mut attr = String::new();
+    let mut kanji = Kanji::default();
+
+    loop {
+        match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => {
+                tag = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                // r_type of a reading, m_lang of a meaning
+                let name: &[u8] = match tag.as_str() {
+                    "reading" => b"r_type",
+                    _ => b"m_lang",
+                };
+                attr = e
+                    .try_get_attribute(name)?
+                    .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+                    .unwrap_or_default();
+            }
+            Event::Text(e) => {
+                let text = e.unescape()?.into_owned();
+                let number = || text.trim().parse::<i64>().ok();
+                match tag.as_str() {
+                    "literal" => kanji.literal = text,
+                    // Later stroke counts are common miscounts
+                    "stroke_count" if kanji.strokes.is_none() => kanji.strokes = number(),
+                    "grade" => kanji.grade = number(),
+                    "jlpt" => kanji.jlpt = number(),
+                    "reading" if attr == "ja_on" => kanji.onyomi.push(text),
+                    "reading" if attr == "ja_kun" => kanji.kunyomi.push(text),
+                    "meaning" if attr == lang || (attr.is_empty() && lang == "en") => {
+                        kanji.meanings.push(text)
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                if e.name().as_ref() == b"character" {
+                    characters.push(std::mem::take(&mut kanji));
+                }
+                tag.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(characters)
+}
+
+// Replaces the stored characters
+pub fn store(
+    conn: &mut Connection,
+    characters: &[Kanji],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    let (sql, values) = Query::delete()
+        .from_table(Alias::new("dict_kanji"))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    tx.execute(&sql, &*values.as_params())?;
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("dict_kanji"))
+        .columns(
+            [
+                "literal", "meanings", "onyomi", "kunyomi", "strokes", "grade", "jlpt",
+            ]
+            .map(Alias::new),
+        )
+        .values_panic([(); 7].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        for kanji in characters {
+            stmt.execute(params![
+                kanji.literal,
+                serde_json::to_string(&kanji.meanings)?,
+                serde_json::to_string(&kanji.onyomi)?,
+                serde_json::to_string(&kanji.kunyomi)?,
+                kanji.strokes,
+                kanji.grade,
+                kanji.jlpt,
+            ])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(characters.len())
+}
+
+pub fn lookup(conn: &Connection, literal: char) -> rusqlite::Result<Option<Kanji>> {
+    let (sql, values) = Query::select()
+        .columns(
+            [
+                "literal", "meanings", "onyomi", "kunyomi", "strokes", "grade", "jlpt",
+            ]
+            .map(Alias::new),
+        )
+        .from(Alias::new("dict_kanji"))
+        .and_where(Expr::col(Alias::new("literal")).eq(literal.to_string()))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    conn.query_row(&sql, &*values.as_params(), |row| {
+        let list = |i: usize| -> rusqlite::Result<Vec<String>> {
+            Ok(serde_json::from_str(&row.get::<_, String>(i)?).unwrap_or_default())
+        };
+        Ok(Kanji {
+            literal: row.get(0)?,
+            meanings: list(1)?,
+            onyomi: list(2)?,
+            kunyomi: list(3)?,
+            strokes: row.get(4)?,
+            grade: row.get(5)?,
+            jlpt: row.get(6)?,
+        })
+    })
+    .optional()
+}
+
+pub fn count(conn: &Connection) -> rusqlite::Result<i64> {
+    let (sql, values) = Query::select()
+        .expr(Func::count(Expr::col(Alias::new("literal"))))
+        .from(Alias::new("dict_kanji"))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    conn.query_row(&sql, &*values.as_params(), |row| row.get(0))
+}
+
+// One block (HTML) or line (text) per kanji
+pub fn breakdown(characters: &[Kanji], format: MarkupFormat) -> String {
+    let lines: Vec<String> = characters
+        .iter()
+        .map(|kanji| {
+            let mut facts = Vec::new();
+            if let Some(strokes) = kanji.strokes {
+                facts.push(format!("{} strokes", strokes));
+            }
+            if let Some(grade) = kanji.grade {
+                facts.push(format!("grade {}", grade));
+            }
+            if let Some(jlpt) = kanji.jlpt {
+                facts.push(format!("JLPT {}", jlpt));
+            }
+
+            match format {
+                MarkupFormat::Text => format!(
+                    "{} {} | on: {} | kun: {} | {}",
+                    kanji.literal,
+                    kanji.meanings.join(", "),
+                    kanji.onyomi.join(", "),
+                    kanji.kunyomi.join(", "),
+                    facts.join(", ")
+                ),
+                MarkupFormat::Html => format!(
+                    "<div class=\"kanji\"><span class=\"kanji-literal\">{}</span> \
+                     <span class=\"kanji-meanings\">{}</span><br>\
+                     <span class=\"kanji-on\">{}</span> <span class=\"kanji-kun\">{}</span><br>\
+                     <small>{}</small></div>",
+                    escape_html(&kanji.literal),
+                    escape_html(&kanji.meanings.join(", ")),
+                    escape_html(&kanji.onyomi.join("、")),
+                    escape_html(&kanji.kunyomi.join("、")),
+                    facts.join(" · ")
+                ),
+            }
+        })
+        .collect();
+
+    match format {
+        MarkupFormat::Text => lines.join("\n"),
+        MarkupFormat::Html => lines.concat(),
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use sea_query::{
+    Alias, ColumnDef, Expr, ExprTrait, OnConflict, Order, Query, SqliteQueryBuilder, Table,
+};
+use sea_query_rusqlite::RusqliteBinder;
+use serde_json::Value as JsonValue;
+
+use crate::{examples::add_tag, models::CardMetadata, row_to_raw_hashmap, value::Record};
+
+// Lookups already turned into cards, so the next import only brings new ones
+pub fn create_kindle_sql() -> String {
+    Table::create()
+        .table(Alias::new("kindle_lookup"))
+        .if_not_exists()
+        // LOOKUPS.id, e.g. `CR!…:1234`
+        .col(
+            ColumnDef::new(Alias::new("id"))
+                .string()
+                .not_null()
+                .primary_key(),
+        )
+        .col(
+            ColumnDef::new(Alias::new("imported_at"))
+                .string()
+                .not_null(),
+        )
+        .to_owned()
+        .build(SqliteQueryBuilder)
+}
+
+// One Vocabulary Builder lookup
+struct Lookup {
+    id: String,
+    stem: String,
+    usage: String,
+    book: String,
+}
+
+fn read_lookups(filename: &str, lang: &str) -> Result<Vec<Lookup>, Box<dyn std::error::Error>> {
+    let vocab = Connection::open_with_flags(filename, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let (lookups, words, books) = (
+        Alias::new("LOOKUPS"),
+        Alias::new("WORDS"),
+        Alias::new("BOOK_INFO"),
+    );
+    let (sql, values) = Query::select()
+        .column((lookups.clone(), Alias::new("id")))
+        .column((words.clone(), Alias::new("word")))
+        .column((words.clone(), Alias::new("stem")))
+        .column((lookups.clone(), Alias::new("usage")))
+        .column((books.clone(), Alias::new("title")))
+        .from(lookups.clone())
+        .inner_join(
+            words.clone(),
+            Expr::col((words.clone(), Alias::new("id")))
+                .equals((lookups.clone(), Alias::new("word_key"))),
+        )
+        .left_join(
+            books.clone(),
+            Expr::col((books.clone(), Alias::new("id")))
+                .equals((lookups.clone(), Alias::new("book_key"))),
+        )
+        .and_where(Expr::col((words, Alias::new("lang"))).eq(lang))
+        // Oldest first, so a word keeps the sentence it was first looked up in
+        .order_by((lookups, Alias::new("timestamp")), Order::Asc)
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    let mut stmt = vocab.prepare(&sql)?;
+    let rows = stmt
+        .query_map(&*values.as_params(), |row| {
+            let word: String = row.get(1)?;
+            let stem: Option<String> = row.get(2)?;
+            Ok(Lookup {
+                id: row.get(0)?,
+                // Older devices leave the stem empty
+                stem: stem.filter(|stem| !stem.trim().is_empty()).unwrap_or(word),
+                usage: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                book: row
+                    .get::<_, Option<String>>(4)?
+                    .unwrap_or_else(|| "Kindle".to_string()),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(rows)
+}
+
+fn imported_ids(conn: &Connection) -> rusqlite::Result<HashSet<String>> {
+    let (sql, values) = Query::select()
+        .column(Alias::new("id"))
+        .from(Alias::new("kindle_lookup"))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    let mut stmt = conn.prepare(&sql)?;
+    stmt.query_map(&*values.as_params(), |row| row.get(0))?
+        .collect()
+}
+
+// The new lookups of one book, its title is the import source
+pub struct Book {
+    pub title: String,
+    pub records: Vec<Record>,
+    // Lookup ids behind each record, remembered once the record is stored
+    pub lookups: Vec<Vec<String>>,
+}
+
+pub fn read(
+    conn: &Connection,
+    card_metadata: &CardMetadata,
+    filename: &str,
+    lang: &str,
+    all: bool,
+) -> Result<Vec<Book>, Box<dyn std::error::Error>> {
+    let imported = match all {
+        true => HashSet::new(),
+        false => imported_ids(conn)?,
+    };
+    let key = card_metadata.get_main_key();
+    let sentence_field = card_metadata
+        .fields
+        .iter()
+        .find(|field| field.metadata.example_for.is_some());
+    // The title goes to a `Source: file` field (set by the import), or else is a tag
+    let tags_field = match card_metadata
+        .fields
+        .iter()
+        .any(|field| field.is_source() && !field.is_clip())
+    {
+        true => None,
+        false => {
+            let tags_field = card_metadata.fields.iter().find(|field| field.is_tags());
+            if tags_field.is_none() {
+                eprintln!("⚠️ No `Source: file` or `Tags` field, book titles aren't kept");
+            }
+            tags_field
+        }
+    };
+
+    let mut books: Vec<Book> = Vec::new();
+    // Book and record of each word
+    let mut seen: HashMap<String, (usize, usize)> = HashMap::new();
+    for lookup in read_lookups(filename, lang)? {
+        if imported.contains(&lookup.id) {
+            continue;
+        }
+
+        // The same word looked up again adds nothing but its id
+        if let Some(&(book, record)) = seen.get(&lookup.stem) {
+            books[book].lookups[record].push(lookup.id);
+            continue;
+        }
+
+        let mut record = Record::new();
+        record.insert(key.name.clone(), JsonValue::String(lookup.stem.clone()));
+        if let Some(field) = sentence_field {
+            record.insert(
+                field.name.clone(),
+                JsonValue::String(lookup.usage.trim().to_string()),
+            );
+        }
+        if let Some(field) = tags_field {
+            // Added to the stored tags; tags are space separated
+            let tag = lookup.book.split_whitespace().collect::<Vec<_>>().join("_");
+            let (sql, values) = card_metadata.get_card_by_key_sql(&key.get_alias(), &lookup.stem);
+            let stored = conn
+                .query_row(&sql, &*values.as_params(), row_to_raw_hashmap)
+                .optional()?
+                .and_then(|mut card| card.remove(&field.name))
+                .unwrap_or_default();
+            record.insert(field.name.clone(), add_tag(field, &stored, &tag));
+        }
+
+        let book = match books.iter().position(|book| book.title == lookup.book) {
+            Some(book) => book,
+            None => {
+                books.push(Book {
+                    title: lookup.book,
+                    records: Vec::new(),
+                    lookups: Vec::new(),
+                });
+                books.len() - 1
+            }
+        };
+        seen.insert(lookup.stem, (book, books[book].records.len()));
+        books[book].records.push(record);
+        books[book].lookups.push(vec![lookup.id]);
+    }
+
+    Ok(books)
+}
+
+pub fn remember(
+    conn: &mut Connection,
+    ids: &[String],
+    now: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("kindle_lookup"))
+        .columns(["id", "imported_at"].map(Alias::new))
+        .values_panic([(); 2].map(|_| Expr::value("")))
+        .on_conflict(OnConflict::column(Alias::new("id")).do_nothing().to_owned())
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        for id in ids {
+            stmt.execute(params![id, now])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(())
+}
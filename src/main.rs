@@ -1,15 +1,45 @@
 use autoruby::annotate::Annotator;
 use clap::Parser;
-use rusqlite::{Row, types::ValueRef};
+use rusqlite::{Row, types::Value};
+use serde_json::Value as JsonValue;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    path::Path,
     sync::{LazyLock, OnceLock},
 };
 
-use crate::models::CardMetadata;
+use crate::{
+    db::{self, write_rows},
+    dedupe::{DuplicateIndex, MergeStrategy},
+    dictionary::DictAction,
+    history::ExportsAction,
+    media::MediaAction,
+    models::{CardMetadata, ExportFormat, ExportTarget, MergePolicy},
+    template::TemplateAction,
+    validate::Validator,
+    value::Record,
+};
 
+mod add;
 mod db;
+mod dedupe;
+mod dictionary;
+mod examples;
+mod frequency;
+mod history;
+mod json;
+mod kanjidic;
+mod kindle;
+mod media;
+mod mine;
 mod models;
+mod pitch;
+mod render;
+mod subtitles;
+mod template;
+mod validate;
+mod value;
+mod yomitan;
 
 // JP Annotator
 pub static AUTORUBY: OnceLock<Annotator> = OnceLock::new();
@@ -42,19 +72,181 @@ fn annotate(text: &str) -> String {
         .to_string()
 }
 
+// How imported values are merged into existing cards
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+    /// For fields without a `Merge` policy in the template
+    #[arg(long, value_enum, default_value_t = MergePolicy::Overwrite)]
+    merge: MergePolicy,
+    /// FIELD=POLICY, wins over the template
+    #[arg(long = "merge-field", value_parser = parse_merge_field)]
+    merge_fields: Vec<(String, MergePolicy)>,
+}
+
+// Which unexported cards go out, and in what order
+#[derive(clap::Args, Debug)]
+struct OrderArgs {
+    /// Field to sort by (e.g. frequency), overrides the target's `sort`
+    #[arg(long)]
+    sort: Option<String>,
+    /// Leave out words ranked rarer than this, overrides the target's `max_rank`
+    #[arg(long)]
+    max_rank: Option<i64>,
+}
+
+fn parse_merge_field(arg: &str) -> Result<(String, MergePolicy), String> {
+    let (field, policy) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=POLICY, got '{arg}'"))?;
+    let policy = <MergePolicy as clap::ValueEnum>::from_str(policy, true)?;
+    Ok((field.to_string(), policy))
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about = "AnkiKaji CLI — Simple Japanese card manager")]
 enum Command {
-    ImportYaml,
+    /// Cards from cards.yaml
+    ImportYaml {
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// Array of card objects
+    ImportJson {
+        file: String,
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// One card object per line
+    ImportJsonl {
+        file: String,
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// Kindle Vocabulary Builder lookups, new ones only
+    ImportKindle {
+        file: String,
+        /// WORDS.lang of the lookups to import
+        #[arg(short, long, default_value = "ja")]
+        lang: String,
+        /// Also lookups imported before
+        #[arg(long)]
+        all: bool,
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// Example sentences from SRT/ASS subtitles, with the time they are heard
+    ImportSubs {
+        file: String,
+        /// New words of the subtitles instead of stored words
+        #[arg(long)]
+        mine: bool,
+        /// With --mine: words to leave out (first column)
+        #[arg(short, long)]
+        known: Option<String>,
+        /// With --mine: keep the N best ranked words
+        #[arg(short, long)]
+        limit: Option<usize>,
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// Enter one card, with a preview of its computed fields
+    Add,
+    /// Export unexported cards to a CSV file
     ExportCsv {
         #[arg(short, long, default_value = "export.csv")]
         file: String,
+        /// Append a `new`/`updated` column for note updates downstream
+        #[arg(long)]
+        status: bool,
+        /// Export profile of the template
+        #[arg(short, long)]
+        profile: Option<String>,
+        #[command(flatten)]
+        order: OrderArgs,
+    },
+    /// Export to a target defined in the template
+    Export {
+        #[arg(short, long, default_value = "default")]
+        target: String,
+        /// Overrides the target's format
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        /// Overrides the target's file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Append a `new`/`updated` column
+        #[arg(long)]
+        status: bool,
+        #[command(flatten)]
+        order: OrderArgs,
+    },
+    /// Find near-duplicate cards and merge them
+    Dedupe {
+        #[arg(short, long, value_enum, default_value_t = MergeStrategy::Report)]
+        strategy: MergeStrategy,
+    },
+    /// Past export batches
+    Exports {
+        #[command(subcommand)]
+        action: ExportsAction,
+    },
+    /// Check or start a template
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
+    /// Fill empty example sentences from the Tatoeba index
+    Examples {
+        /// Words whose kanji count as known (first column)
+        #[arg(short, long)]
+        known: Option<String>,
+        /// Added to the `Tags` field of filled cards
+        #[arg(short, long, default_value = "auto-example")]
+        tag: String,
+        /// Only print the picks
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Candidate cards from a Japanese text, as a cards.yaml fragment
+    Mine {
+        file: String,
+        /// Words to leave out (first column)
+        #[arg(short, long)]
+        known: Option<String>,
+        /// Keep the N best ranked words
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Write the fragment here instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Import the candidates instead of printing them
+        #[arg(long)]
+        db: bool,
+        #[command(flatten)]
+        merge: MergeArgs,
+    },
+    /// Audio clips and screenshots cut from local media with ffmpeg
+    Media {
+        #[command(subcommand)]
+        action: MediaAction,
+    },
+    /// Dictionaries for `Dictionary` fields
+    Dict {
+        #[command(subcommand)]
+        action: DictAction,
     },
+    /// Check cards against the template's rules, stored ones unless a file is given
+    Validate { file: Option<String> },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let command = Command::parse();
 
+    // No DB, and the template may not exist or be broken yet
+    if let Command::Template { action } = command {
+        return template::template(action);
+    }
+
     let mut conn = rusqlite::Connection::open(ANKIKAJI_DB.as_str())?;
     let card_metadata = CardMetadata::from_yaml("jp-template.yaml")?;
 
@@ -62,104 +254,408 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     {
         let sql = card_metadata.create_table_sql();
         conn.execute(&sql, [])?;
+
+        for sql in card_metadata.create_export_history_sql() {
+            conn.execute(&sql, [])?;
+        }
+
+        let columns = table_columns(&conn, "dict_jlpt")?;
+        for sql in frequency::upgrade_jlpt_sql(&columns) {
+            conn.execute(&sql, [])?;
+        }
+        for sql in dictionary::create_dictionary_sql() {
+            conn.execute(&sql, [])?;
+        }
+        conn.execute(&kindle::create_kindle_sql(), [])?;
+
+        let columns = table_columns(&conn, ANKIKAJI_TABLE.as_str())?;
+        for sql in card_metadata.add_missing_columns_sql(&columns)? {
+            conn.execute(&sql, [])?;
+        }
+
+        let columns = table_columns(&conn, &db::export_batch_table().0)?;
+        for sql in card_metadata.add_export_batch_columns_sql(&columns) {
+            conn.execute(&sql, [])?;
+        }
     }
 
     match command {
-        Command::ImportYaml => {
-            import_yaml(&mut conn, card_metadata, "cards.yaml")?;
-        }
-        Command::ExportCsv { file } => {
-            let (sql, values) = card_metadata.get_unexported_cards_sql();
-            let mut stmt = conn.prepare(&sql)?;
-            let cards = stmt
-                .query_map(&*values.as_params(), |row| row_to_hashmap(row))?
-                .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
-            drop(stmt);
-
-            if cards.is_empty() {
-                println!("No new cards to export.");
-                return Ok(());
+        Command::ImportYaml { merge } => {
+            let file_content = std::fs::read_to_string("cards.yaml")?;
+            let cards: Vec<Record> = serde_yaml::from_str(&file_content)?;
+            import_records(&mut conn, &card_metadata, &cards, "cards.yaml", &merge)?;
+        }
+        Command::ImportJson { file, merge } => {
+            let cards = json::read_json(&file, false)?;
+            import_records(&mut conn, &card_metadata, &cards, &file, &merge)?;
+        }
+        Command::ImportJsonl { file, merge } => {
+            let cards = json::read_json(&file, true)?;
+            import_records(&mut conn, &card_metadata, &cards, &file, &merge)?;
+        }
+        Command::ImportKindle {
+            file,
+            lang,
+            all,
+            merge,
+        } => {
+            let books = kindle::read(&conn, &card_metadata, &file, &lang, all)?;
+            if books.is_empty() {
+                println!("No new lookups.");
             }
+            // The book title is the source (or a tag) of its cards; skipped lookups are tried again
+            for book in &books {
+                println!("📖 {} ({} words)", book.title, book.records.len());
+                let stored = import_records(
+                    &mut conn,
+                    &card_metadata,
+                    &book.records,
+                    &book.title,
+                    &merge,
+                )?;
+                let ids: Vec<String> = stored
+                    .into_iter()
+                    .flat_map(|i| book.lookups[i].iter().cloned())
+                    .collect();
+                kindle::remember(&mut conn, &ids, &value::now())?;
+            }
+        }
+        Command::ImportSubs {
+            file,
+            mine,
+            known,
+            limit,
+            merge,
+        } => {
+            let cards = subtitles::read_records(
+                &conn,
+                &card_metadata,
+                &file,
+                mine,
+                known.as_deref(),
+                limit,
+            )?;
+            import_records(&mut conn, &card_metadata, &cards, &file, &merge)?;
+        }
+        Command::Add => {
+            add::add_card(&mut conn, &card_metadata)?;
+        }
+        Command::ExportCsv {
+            file,
+            status,
+            profile,
+            order,
+        } => {
+            let target = ExportTarget {
+                name: "default".to_string(),
+                format: ExportFormat::Csv,
+                fields: Vec::new(),
+                profile,
+                file,
+                sort: order.sort,
+                max_rank: order.max_rank,
+            };
+            export_cards(&mut conn, &card_metadata, &target, status)?;
+        }
+        Command::Export {
+            target,
+            format,
+            file,
+            status,
+            order,
+        } => {
+            let mut target = match card_metadata.get_target(&target) {
+                Some(target) => target.clone(),
+                None if target == "default" => ExportTarget {
+                    name: target,
+                    format: ExportFormat::Csv,
+                    fields: Vec::new(),
+                    profile: None,
+                    file: "export.csv".to_string(),
+                    sort: None,
+                    max_rank: None,
+                },
+                None => return Err(format!("unknown export target '{target}'").into()),
+            };
 
-            let key = card_metadata.get_main_key();
-
-            card_metadata.export_csv(&cards, &file)?;
-
-            let words: Vec<String> = cards
-                .iter()
-                .map(|c| c.get(key.name.as_str()).unwrap().clone())
-                .collect();
-
-            // Mark all cards as exported
-            let sqls_values = card_metadata.mark_unexported_cards_tx(&key.get_alias(), &words);
-            let tx = conn.transaction()?;
-            for (sql, values) in sqls_values {
-                tx.execute(&sql, &*values.as_params())?;
+            if let Some(format) = format {
+                target.format = format;
+                target.file = Path::new(&target.file)
+                    .with_extension(format.extension())
+                    .to_string_lossy()
+                    .to_string();
+            }
+            if let Some(file) = file {
+                target.file = file;
+            }
+            if order.sort.is_some() {
+                target.sort = order.sort;
+            }
+            if order.max_rank.is_some() {
+                target.max_rank = order.max_rank;
             }
-            tx.commit()?;
 
-            println!("✅ Exported {} cards to '{}'", words.len(), file);
+            export_cards(&mut conn, &card_metadata, &target, status)?;
+        }
+        Command::Dedupe { strategy } => {
+            dedupe::dedupe(&mut conn, &card_metadata, strategy)?;
+        }
+        Command::Exports { action } => {
+            history::exports(&mut conn, &card_metadata, action)?;
+        }
+        Command::Template { .. } => unreachable!(),
+        Command::Examples {
+            known,
+            tag,
+            dry_run,
+        } => {
+            examples::examples(&mut conn, &card_metadata, known.as_deref(), &tag, dry_run)?;
+        }
+        Command::Mine {
+            file,
+            known,
+            limit,
+            output,
+            db,
+            merge,
+        } => {
+            let cards = mine::mine(&conn, &card_metadata, &file, known.as_deref(), limit)?;
+            if db {
+                import_records(&mut conn, &card_metadata, &cards, &file, &merge)?;
+            } else {
+                let fragment = mine::to_yaml(&card_metadata, &cards)?;
+                match output {
+                    Some(output) => {
+                        std::fs::write(&output, fragment)?;
+                        println!("✅ Wrote {} candidates to {}", cards.len(), output);
+                    }
+                    None => print!("{}", fragment),
+                }
+            }
+        }
+        Command::Media { action } => {
+            media::media(&mut conn, &card_metadata, action)?;
+        }
+        Command::Dict { action } => {
+            dictionary::dict(&mut conn, action)?;
+        }
+        Command::Validate { file } => {
+            let records = match file.as_deref() {
+                None => None,
+                Some(file) if file.ends_with(".jsonl") => Some(json::read_json(file, true)?),
+                Some(file) if file.ends_with(".json") => Some(json::read_json(file, false)?),
+                Some(file) => Some(serde_yaml::from_str(&std::fs::read_to_string(file)?)?),
+            };
+            validate::validate(&conn, &card_metadata, records.as_deref())?;
         }
     }
 
     Ok(())
 }
 
-// Import records from yaml to SQLite DB
-fn import_yaml(
+fn table_columns(conn: &rusqlite::Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    stmt.query_map([table], |row| row.get::<_, String>(0))?
+        .collect()
+}
+
+// Import records (from yaml, json, ...) to SQLite DB
+fn import_records(
     conn: &mut rusqlite::Connection,
-    card_metadata: CardMetadata,
-    filename: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let file_content = std::fs::read_to_string(filename)?;
-    let cards: Vec<HashMap<String, String>> = serde_yaml::from_str(&file_content)?;
+    card_metadata: &CardMetadata,
+    cards: &[Record],
+    source: &str,
+    merge: &MergeArgs,
+) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+    let merge = card_metadata.merge_policies(merge.merge, &merge.merge_fields)?;
+    let validator = Validator::new(card_metadata)?;
+    let now = value::now();
+
+    // Near-duplicate check against what is already stored
+    let key = card_metadata.get_main_key();
+    let mut duplicates = {
+        let (sql, values) = card_metadata.get_all_cards_sql();
+        let mut stmt = conn.prepare(&sql)?;
+        let existing = stmt
+            .query_map(&*values.as_params(), row_to_raw_hashmap)?
+            .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
+        DuplicateIndex::new(
+            existing
+                .iter()
+                .filter_map(|card| card.get(&key.name))
+                .map(String::as_str),
+        )
+    };
 
     let tx = conn.transaction()?; // start transaction for batch
 
-    for record in cards.iter().as_ref() {
-        let card = card_metadata.get_data_from_record(&record);
+    let mut upserted = Vec::new();
+    for (i, record) in cards.iter().enumerate() {
+        let (record, stamped) = card_metadata.fill_record(record, Some(source), &now);
+        let (fields, values, key) = match card_metadata.get_data_from_record(&record) {
+            Ok(card) => card,
+            Err(e) => {
+                eprintln!("⚠️ Skipped invalid record ({}): {:?}", e, record);
+                continue;
+            }
+        };
 
-        if card.is_none() {
-            eprintln!("⚠️ Skipped invalid record: {:?}", record);
+        let word = record
+            .get(&key.0)
+            .and_then(JsonValue::as_str)
+            .unwrap_or_default();
+        if validate::report(word, &validator.check(&fields, &values)) {
+            eprintln!("⚠️ Skipped rejected record: {}", word);
             continue;
         }
 
-        let (fields, values, key) = card.unwrap();
-        let result = card_metadata.preprocess_data(&fields, &values);
+        if !word.is_empty() {
+            if let Some(other) = duplicates.check(word) {
+                eprintln!(
+                    "⚠️ '{}' looks like a duplicate of '{}' (see `dedupe`)",
+                    word, other
+                );
+            }
+        }
+
+        let result = card_metadata.preprocess_data(&tx, &fields, &values);
         if result.is_none() {
             continue;
         }
         let (fields, values) = result.unwrap();
 
+        let mut merge = merge.clone();
+        merge.extend(stamped);
         let (sql, params) =
-            card_metadata.insert_or_update_card_with_fields_sql(&key, &fields, &values);
+            card_metadata.insert_or_update_card_with_fields_sql(&key, &fields, &values, &merge);
 
         tx.execute(sql.as_str(), &*params.as_params())?;
+        upserted.push(i);
     }
 
     tx.commit()?;
 
-    println!("Total Upserted: {}", cards.len());
+    println!("Total Upserted: {}", upserted.len());
+
+    // Which of `cards` were stored
+    Ok(upserted)
+}
+
+// Export cards not yet exported to the target and record the batch
+fn export_cards(
+    conn: &mut rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    target: &ExportTarget,
+    status: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let profile = card_metadata.get_export_profile(target)?;
+    card_metadata.check_target_order(target)?;
+
+    let (sql, values) = card_metadata.get_unexported_cards_sql(target);
+    let mut stmt = conn.prepare(&sql)?;
+    let cards = stmt
+        .query_map(&*values.as_params(), row_to_value_map)?
+        .collect::<rusqlite::Result<Vec<HashMap<String, Value>>>>()?;
+    drop(stmt);
+
+    if cards.is_empty() {
+        println!("No new cards to export.");
+        return Ok(());
+    }
+
+    // Cards exported before are updates of existing notes
+    let (sql, values) = card_metadata.get_exported_keys_sql(&target.name);
+    let mut stmt = conn.prepare(&sql)?;
+    let exported = stmt
+        .query_map(&*values.as_params(), |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<String>>>()?;
+    drop(stmt);
+
+    let key = card_metadata.get_main_key();
+
+    let words: Vec<String> = cards
+        .iter()
+        .map(|c| render::plain(c.get(key.name.as_str()).unwrap()))
+        .collect();
+
+    let mut updated = 0;
+    let mut rows = Vec::new();
+    for (card, word) in cards.iter().zip(&words) {
+        let mut row = card_metadata.export_row(&profile, target.format, card)?;
+        let card_status = if exported.contains(word) {
+            updated += 1;
+            "updated"
+        } else {
+            "new"
+        };
+
+        println!(
+            "  {} {}",
+            if card_status == "new" { "+" } else { "~" },
+            word
+        );
+        match (&mut row, status) {
+            (JsonValue::Array(values), true) => values.push(card_status.into()),
+            (JsonValue::Object(object), true) => {
+                object.insert("status".to_string(), card_status.into());
+            }
+            _ => {}
+        }
+        rows.push(row);
+    }
+
+    let mut header = profile.get_header();
+    if let (Some(header), true) = (&mut header, status) {
+        header.push("status".to_string());
+    }
+    write_rows(header.as_deref(), &rows, &target.file, target.format)?;
+
+    // Mark all cards as exported
+    let sqls_values =
+        card_metadata.mark_unexported_cards_tx(&key.get_alias(), &target.name, &words);
+    let tx = conn.transaction()?;
+    for (sql, values) in sqls_values {
+        tx.execute(&sql, &*values.as_params())?;
+    }
+    let batch_id = history::record_batch(
+        &tx,
+        card_metadata,
+        &target.name,
+        &target.file,
+        target.format,
+        header.as_deref(),
+        &words,
+        &rows,
+    )?;
+    tx.commit()?;
+
+    println!(
+        "✅ Exported {} cards to '{}' ({} new, {} updated, batch #{})",
+        words.len(),
+        target.file,
+        words.len() - updated,
+        updated,
+        batch_id
+    );
 
     Ok(())
 }
 
-// Convert Row (from SQLite) to Hashmap
-fn row_to_hashmap(row: &Row) -> rusqlite::Result<HashMap<String, String>> {
+// Convert Row (from SQLite) to Hashmap of typed values, rendered on export
+fn row_to_value_map(row: &Row) -> rusqlite::Result<HashMap<String, Value>> {
     let mut map = HashMap::new();
     for (i, col_name) in row.as_ref().column_names().iter().enumerate() {
-        let val_ref = row.get_ref(i)?;
-        let value_str = match val_ref {
-            ValueRef::Null => "".to_string(),
-            ValueRef::Integer(i) => i.to_string(),
-            ValueRef::Real(f) => f.to_string(),
-            ValueRef::Text(t) => String::from_utf8_lossy(t)
-                .replace("\n", "<br>")
-                .trim_end_matches("<br>")
-                .to_string(),
-            ValueRef::Blob(b) => format!("{:?}", b),
-        };
-        map.insert(col_name.to_string(), value_str);
+        map.insert(col_name.to_string(), row.get::<_, Value>(i)?);
+    }
+    Ok(map)
+}
+
+// Convert Row (from SQLite) to Hashmap, keeping values as stored
+fn row_to_raw_hashmap(row: &Row) -> rusqlite::Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for (i, col_name) in row.as_ref().column_names().iter().enumerate() {
+        let value = row.get::<_, Value>(i)?;
+        map.insert(col_name.to_string(), render::plain(&value));
     }
     Ok(map)
 }
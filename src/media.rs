@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    models::{CardMetadata, FieldSpec},
+    render::store_media,
+    row_to_raw_hashmap,
+    subtitles::{parse_clip, timestamp},
+    value::FieldValue,
+};
+
+const VIDEO: [&str; 6] = ["mkv", "mp4", "webm", "avi", "mov", "m4v"];
+const AUDIO: [&str; 6] = ["mp3", "m4a", "ogg", "opus", "flac", "wav"];
+
+#[derive(clap::Subcommand, Debug)]
+pub enum MediaAction {
+    /// Cut the sentence audio and a screenshot for cards with a `Source: clip`
+    Extract {
+        /// Media store, e.g. Anki's collection.media
+        #[arg(short, long, default_value = "media")]
+        dir: String,
+        #[arg(long, default_value = "sentence_audio")]
+        audio_field: String,
+        #[arg(long, default_value = "image")]
+        image_field: String,
+        #[arg(long)]
+        no_audio: bool,
+        #[arg(long)]
+        no_image: bool,
+        /// Also cards that already have audio or an image
+        #[arg(long)]
+        force: bool,
+        /// Only print what would be cut
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub fn media(
+    conn: &mut Connection,
+    card_metadata: &CardMetadata,
+    action: MediaAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        MediaAction::Extract {
+            dir,
+            audio_field,
+            image_field,
+            no_audio,
+            no_image,
+            force,
+            dry_run,
+        } => {
+            let field = |name: &str| {
+                card_metadata
+                    .fields
+                    .iter()
+                    .find(|field| field.name == name)
+                    .ok_or_else(|| format!("no field '{name}' in the template"))
+            };
+            let audio = match no_audio {
+                true => None,
+                false => Some(field(&audio_field)?),
+            };
+            let image = match no_image {
+                true => None,
+                false => Some(field(&image_field)?),
+            };
+            extract(
+                conn,
+                card_metadata,
+                Path::new(&dir),
+                audio,
+                image,
+                force,
+                dry_run,
+            )
+        }
+    }
+}
+
+// The video or audio next to a subtitle file: ep01.ja.srt → ep01.mkv, or the file itself
+fn find_media(file: &str) -> Option<PathBuf> {
+    let path = Path::new(file);
+    if has_extension(path, &VIDEO) || has_extension(path, &AUDIO) {
+        return path.exists().then(|| path.to_path_buf());
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    let stems = [Some(stem), stem.rsplit_once('.').map(|(stem, _)| stem)];
+    stems
+        .into_iter()
+        .flatten()
+        .flat_map(|stem| {
+            VIDEO
+                .iter()
+                .chain(&AUDIO)
+                .map(move |ext| path.with_file_name(format!("{stem}.{ext}")))
+        })
+        .find(|candidate| candidate.exists())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+// The ffmpeg binary, `FFMPEG` or from PATH
+fn ffmpeg_binary() -> Result<String, Box<dyn std::error::Error>> {
+    let binary = std::env::var("FFMPEG").unwrap_or_else(|_| "ffmpeg".to_string());
+    match Command::new(&binary).arg("-version").output() {
+        Ok(output) if output.status.success() => Ok(binary),
+        _ => Err(
+            format!("{binary} not found (install ffmpeg, set FFMPEG, or skip this step)").into(),
+        ),
+    }
+}
+
+// Runs ffmpeg writing `extension`, the file's bytes
+fn ffmpeg(
+    binary: &str,
+    args: &[&str],
+    extension: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let out = std::env::temp_dir().join(format!("ankikaji-{}.{}", std::process::id(), extension));
+    let output = Command::new(binary)
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(args)
+        .arg(&out)
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+
+    let blob = std::fs::read(&out)?;
+    std::fs::remove_file(&out)?;
+    Ok(blob)
+}
+
+// Mono mp3 of `duration` seconds from `from`
+fn audio_args<'a>(from: &'a str, duration: &'a str, input: &'a str) -> [&'a str; 13] {
+    [
+        "-ss",
+        from,
+        "-t",
+        duration,
+        "-i",
+        input,
+        "-vn",
+        "-ac",
+        "1",
+        "-c:a",
+        "libmp3lame",
+        "-q:a",
+        "4",
+    ]
+}
+
+fn extract(
+    conn: &mut Connection,
+    card_metadata: &CardMetadata,
+    dir: &Path,
+    audio: Option<&FieldSpec>,
+    image: Option<&FieldSpec>,
+    force: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(clip_field) = card_metadata.fields.iter().find(|field| field.is_clip()) else {
+        return Err("no field has `Source: clip`".into());
+    };
+    let binary = match dry_run {
+        true => String::new(),
+        false => ffmpeg_binary()?,
+    };
+    let key = card_metadata.get_main_key();
+
+    let (sql, values) = card_metadata.get_all_cards_sql();
+    let cards = {
+        let mut stmt = conn.prepare(&sql)?;
+        stmt.query_map(&*values.as_params(), row_to_raw_hashmap)?
+            .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?
+    };
+
+    let tx = conn.transaction()?;
+    let mut extracted = 0;
+    for card in &cards {
+        let Some(clip) = card.get(&clip_field.name).filter(|clip| !clip.is_empty()) else {
+            continue;
+        };
+        let word = &card[&key.name];
+        let Some((file, start, end)) = parse_clip(clip) else {
+            eprintln!("⚠️ {}: can't read clip '{}'", word, clip);
+            continue;
+        };
+        let Some(path) = find_media(file) else {
+            eprintln!("⚠️ {}: no video or audio found for {}", word, file);
+            continue;
+        };
+
+        let wanted =
+            |field: &FieldSpec| force || card.get(&field.name).is_none_or(|value| value.is_empty());
+        let audio = audio.filter(|field| wanted(field));
+        // Audio files have nothing to screenshot
+        let image = image.filter(|field| wanted(field) && has_extension(&path, &VIDEO));
+        if audio.is_none() && image.is_none() {
+            continue;
+        }
+
+        let input = path.to_string_lossy().to_string();
+        let (from, duration) = (
+            timestamp(start),
+            format!("{:.3}", end.saturating_sub(start) as f64 / 1000.0),
+        );
+        let middle = timestamp(start + end.saturating_sub(start) / 2);
+        println!("{}: {} {}-{}", word, input, from, timestamp(end));
+        if dry_run {
+            continue;
+        }
+
+        let mut fields = vec![key.get_alias()];
+        let mut values = vec![FieldValue::parse(key, word)?];
+        let mut changed = Vec::new();
+        if let Some(field) = audio {
+            match ffmpeg(&binary, &audio_args(&from, &duration, &input), "mp3") {
+                Ok(blob) => {
+                    let name = store_media(dir, &blob, "mp3")?;
+                    fields.push(field.get_alias());
+                    values.push(FieldValue::parse(field, &format!("[sound:{name}]"))?);
+                    changed.push(field.name.as_str());
+                }
+                Err(e) => eprintln!("⚠️ {}: audio not cut ({})", word, e),
+            }
+        }
+        if let Some(field) = image {
+            let args: &[&str] = &["-ss", &middle, "-i", &input, "-frames:v", "1", "-q:v", "3"];
+            match ffmpeg(&binary, args, "jpg") {
+                Ok(blob) => {
+                    let name = store_media(dir, &blob, "jpg")?;
+                    fields.push(field.get_alias());
+                    // preprocess_data wraps it in `<img src>`
+                    values.push(FieldValue::parse(field, &name)?);
+                    changed.push(field.name.as_str());
+                }
+                Err(e) => eprintln!("⚠️ {}: no screenshot ({})", word, e),
+            }
+        }
+        if changed.is_empty() {
+            continue;
+        }
+
+        let Some((fields, values)) = card_metadata.preprocess_data(&tx, &fields, &values) else {
+            continue;
+        };
+        let (fields, values) = card_metadata.only_changed(&changed, fields, values);
+        let (sql, params) = card_metadata.insert_or_update_card_with_fields_sql(
+            &key.get_alias(),
+            &fields,
+            &values,
+            &HashMap::new(),
+        );
+        tx.execute(sql.as_str(), &*params.as_params())?;
+        extracted += 1;
+    }
+    tx.commit()?;
+
+    match dry_run {
+        true => println!("Dry run, nothing cut."),
+        false => println!("✅ Added media to {} cards in {}", extracted, dir.display()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of its own under the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ankikaji-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn find_media_next_to_subtitles() {
+        let dir = temp_dir("find-media");
+        for file in [
+            "ep01.ja.srt",
+            "ep01.mkv",
+            "ep02.srt",
+            "ep02.mp3",
+            "ep03.srt",
+        ] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let path = |file: &str| dir.join(file).to_string_lossy().to_string();
+
+        // Second extension dropped
+        assert_eq!(find_media(&path("ep01.ja.srt")), Some(dir.join("ep01.mkv")));
+        assert_eq!(find_media(&path("ep02.srt")), Some(dir.join("ep02.mp3")));
+        assert_eq!(find_media(&path("ep03.srt")), None);
+        // A media file is its own media
+        assert_eq!(find_media(&path("ep01.mkv")), Some(dir.join("ep01.mkv")));
+        assert_eq!(find_media(&path("ep04.mkv")), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn has_extension_ignores_case() {
+        assert!(has_extension(Path::new("ep01.MKV"), &VIDEO));
+        assert!(!has_extension(Path::new("ep01.srt"), &VIDEO));
+        assert!(!has_extension(Path::new("ep01"), &AUDIO));
+    }
+
+    // 1 s of a 440 Hz tone
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/clip.wav");
+
+    #[test]
+    fn ffmpeg_cuts_audio() {
+        let Ok(binary) = ffmpeg_binary() else {
+            eprintln!("skipped: no ffmpeg");
+            return;
+        };
+
+        let (from, duration) = (timestamp(250), "0.500");
+        let blob = ffmpeg(&binary, &audio_args(&from, duration, FIXTURE), "mp3").unwrap();
+        assert!(!blob.is_empty());
+
+        // A missing input is an error, not an empty file
+        let missing = format!("{FIXTURE}.missing");
+        assert!(ffmpeg(&binary, &audio_args(&from, duration, &missing), "mp3").is_err());
+    }
+}
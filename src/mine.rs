@@ -0,0 +1,248 @@
+use std::collections::{HashMap, HashSet};
+
+use lindera::{
+    dictionary::{DictionaryKind, load_dictionary_from_kind},
+    mode::Mode,
+    segmenter::Segmenter,
+    tokenizer::Tokenizer,
+};
+use serde_json::Value as JsonValue;
+
+use crate::{
+    dedupe::normalize,
+    frequency,
+    models::CardMetadata,
+    row_to_raw_hashmap,
+    validate::{is_kana, is_kanji},
+    value::Record,
+};
+
+// A word found in the text, with the first sentence it was seen in (index)
+struct Candidate {
+    lemma: String,
+    sentence: usize,
+    count: usize,
+    rank: Option<i64>,
+}
+
+// IPADIC part of speech (and subclass) worth a card
+fn is_content_word(pos: &str, subclass: &str) -> bool {
+    match pos {
+        "名詞" => !matches!(subclass, "数" | "代名詞" | "非自立" | "接尾" | "固有名詞"),
+        "動詞" | "形容詞" => subclass != "非自立",
+        "副詞" => true,
+        // Particles, auxiliaries, symbols, conjunctions, …
+        _ => false,
+    }
+}
+
+// Same dictionary as autoruby
+pub fn tokenizer() -> Result<Tokenizer, Box<dyn std::error::Error>> {
+    let dictionary = load_dictionary_from_kind(DictionaryKind::IPADIC)?;
+    Ok(Tokenizer::new(Segmenter::new(
+        Mode::Normal,
+        dictionary,
+        None,
+    )))
+}
+
+// Dictionary forms of the Japanese words of a text, each once
+pub fn lemmas(
+    tokenizer: &Tokenizer,
+    text: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut lemmas: Vec<String> = Vec::new();
+    for token in tokenizer.tokenize(text)?.iter_mut() {
+        let surface = token.text.to_string();
+        let details: Vec<String> = token.details().iter().map(|s| s.to_string()).collect();
+        // `*` when the word is unknown
+        let lemma = match details.get(6).map(String::as_str) {
+            Some(lemma) if lemma != "*" => lemma.to_string(),
+            _ => surface,
+        };
+        if lemma.chars().any(|c| is_kanji(c) || is_kana(c)) && !lemmas.contains(&lemma) {
+            lemmas.push(lemma);
+        }
+    }
+    Ok(lemmas)
+}
+
+// Sentences end at 。！？ or a line break; the end mark stays
+fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if matches!(c, '。' | '！' | '？' | '!' | '?' | '\n') {
+            let end = i + c.len_utf8();
+            sentences.push(text[start..end].trim());
+            start = end;
+        }
+    }
+    sentences.push(text[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+// Candidate cards from a text, most frequent words first
+pub fn mine(
+    conn: &rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    file: &str,
+    known: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Vec<Record>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(file)?;
+    let sentences = sentences(&text);
+
+    Ok(candidates(conn, card_metadata, &sentences, known, limit)?
+        .into_iter()
+        .map(|(word, i)| record(card_metadata, word, sentences[i]))
+        .collect())
+}
+
+// New words of the sentences, each with the sentence it was first seen in (by index)
+pub fn candidates(
+    conn: &rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    sentences: &[&str],
+    known: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Vec<(String, usize)>, Box<dyn std::error::Error>> {
+    let tokenizer = tokenizer()?;
+
+    // Stored keys and the known-words list
+    let key = card_metadata.get_main_key();
+    let mut skip: HashSet<String> = HashSet::new();
+    {
+        let (sql, values) = card_metadata.get_all_cards_sql();
+        let mut stmt = conn.prepare(&sql)?;
+        let cards = stmt
+            .query_map(&*values.as_params(), row_to_raw_hashmap)?
+            .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
+        skip.extend(
+            cards
+                .iter()
+                .filter_map(|card| card.get(&key.name))
+                .map(|word| normalize(word)),
+        );
+    }
+    if let Some(known) = known {
+        skip.extend(
+            std::fs::read_to_string(known)?
+                .lines()
+                .filter_map(|line| line.split(['\t', ',']).next())
+                .map(normalize)
+                .filter(|word| !word.is_empty()),
+        );
+    }
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (i, sentence) in sentences.iter().enumerate() {
+        let mut tokens = tokenizer.tokenize(sentence)?;
+        for token in tokens.iter_mut() {
+            let surface = token.text.to_string();
+            let details: Vec<String> = token.details().iter().map(|s| s.to_string()).collect();
+            let pos = details.first().map(String::as_str).unwrap_or_default();
+            let subclass = details.get(1).map(String::as_str).unwrap_or_default();
+            if !is_content_word(pos, subclass) {
+                continue;
+            }
+
+            // Dictionary form, `*` when the word is unknown
+            let lemma = match details.get(6).map(String::as_str) {
+                Some(lemma) if lemma != "*" => lemma.to_string(),
+                _ => surface,
+            };
+            if !lemma.chars().any(|c| is_kanji(c) || is_kana(c))
+                || skip.contains(&normalize(&lemma))
+            {
+                continue;
+            }
+
+            match positions.get(&lemma) {
+                Some(&index) => candidates[index].count += 1,
+                None => {
+                    positions.insert(lemma.clone(), candidates.len());
+                    candidates.push(Candidate {
+                        lemma,
+                        sentence: i,
+                        count: 1,
+                        rank: None,
+                    });
+                }
+            }
+        }
+    }
+
+    // Ranked by the template's frequency lists, then by count in the text
+    let lists = card_metadata
+        .get_frequency_field()
+        .map(|field| field.frequency_lists())
+        .unwrap_or_default();
+    for candidate in &mut candidates {
+        for list in &lists {
+            if let Some(rank) = frequency::rank(conn, list, &candidate.lemma)? {
+                candidate.rank = Some(rank);
+                break;
+            }
+        }
+    }
+    candidates.sort_by_key(|candidate| {
+        (
+            candidate.rank.is_none(),
+            candidate.rank,
+            std::cmp::Reverse(candidate.count),
+        )
+    });
+    if let Some(limit) = limit {
+        candidates.truncate(limit);
+    }
+
+    Ok(candidates
+        .into_iter()
+        .map(|candidate| (candidate.lemma, candidate.sentence))
+        .collect())
+}
+
+// The key and the `Example For` field
+pub fn record(card_metadata: &CardMetadata, word: String, sentence: &str) -> Record {
+    let mut record = Record::new();
+    record.insert(
+        card_metadata.get_main_key().name.clone(),
+        JsonValue::String(word),
+    );
+    if let Some(field) = card_metadata
+        .fields
+        .iter()
+        .find(|field| field.metadata.example_for.is_some())
+    {
+        record.insert(field.name.clone(), JsonValue::String(sentence.to_string()));
+    }
+    record
+}
+
+// A cards.yaml fragment, fields in template order
+pub fn to_yaml(
+    card_metadata: &CardMetadata,
+    records: &[Record],
+) -> Result<String, serde_yaml::Error> {
+    let cards: Vec<serde_yaml::Mapping> = records
+        .iter()
+        .map(|record| {
+            card_metadata
+                .fields
+                .iter()
+                .filter_map(|field| {
+                    let value = record.get(&field.name)?;
+                    Some((
+                        serde_yaml::Value::from(field.name.clone()),
+                        serde_yaml::to_value(value).ok()?,
+                    ))
+                })
+                .collect()
+        })
+        .collect();
+
+    serde_yaml::to_string(&cards)
+}
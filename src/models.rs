@@ -1,9 +1,14 @@
 use std::{collections::HashMap, error::Error};
 
-use sea_query::{Alias, ColumnDef};
+use sea_query::{Alias, ColumnDef, Expr, ExprTrait};
 use serde::Deserialize;
+use serde_json::Value as JsonValue;
 
-use crate::annotate;
+use crate::{
+    annotate, dictionary, frequency, kanjidic, pitch,
+    validate::{self, Severity, Validator},
+    value::{FieldValue, Record},
+};
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub enum FieldType {
@@ -12,14 +17,197 @@ pub enum FieldType {
     Text,
     Boolean,
     Integer,
+    Float,
+    // YYYY-MM-DD
+    Date,
+    // YYYY-MM-DD HH:MM:SS
+    DateTime,
+    // One of the `Values` in the metadata
+    Enum,
+    // Stored as a JSON array, joined on export
+    List,
+    Json,
+}
+
+// How an upsert combines an incoming value with the stored one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum MergePolicy {
+    /// Incoming value wins, `null` clears
+    #[default]
+    Overwrite,
+    /// Stored value wins over null or blank input
+    #[serde(rename = "Keep If Empty")]
+    KeepIfEmpty,
+    /// New text goes on a new line, e.g. another definition
+    Append,
+    /// Emptied by null or blank input, other input keeps the stored value
+    Clear,
+    /// Stored value wins, only empty fields are filled
+    Fill,
+    /// Moves only when another column changes, for `Now: updated`
+    #[value(skip)]
+    #[serde(skip)]
+    Touch,
+}
+
+// Value of `Now:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stamp {
+    Created,
+    Updated,
+}
+
+// Value of `Source:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceStamp {
+    File,
+    // Subtitle file and time span of the example sentence, set by `import-subs`
+    Clip,
+}
+
+// Value of `Entry:`, the part of a dictionary entry a field takes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum DictionaryEntry {
+    // Definitions, numbered if there are several senses
+    #[default]
+    Gloss,
+    // Part of speech codes, e.g. v1, n
+    Pos,
+    // JMdict's common flag
+    Common,
+}
+
+// Value of `Format:`, how a derived field is written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum MarkupFormat {
+    #[default]
+    Html,
+    Text,
+}
+
+// `Values: [N5, N4]` or `Values: N5, N4`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ValueList {
+    List(Vec<String>),
+    Text(String),
+}
+
+impl ValueList {
+    #[inline]
+    pub fn items(&self) -> Vec<&str> {
+        match self {
+            ValueList::List(values) => values.iter().map(String::as_str).collect(),
+            ValueList::Text(values) => values.split(',').map(str::trim).collect(),
+        }
+    }
+}
+
+// Known field options; anything else is a template error
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldMetadata {
+    #[serde(rename = "Primary Key")]
+    pub primary_key: bool,
+    #[serde(rename = "Key")]
+    pub key: bool,
+    #[serde(rename = "Auto Increment")]
+    pub auto_increment: bool,
+    #[serde(rename = "Not Null")]
+    pub not_null: bool,
+    #[serde(rename = "Unique")]
+    pub unique: bool,
+    #[serde(rename = "Image")]
+    pub image: bool,
+    #[serde(rename = "Audio")]
+    pub audio: bool,
+
+    // Derived from another field
+    #[serde(rename = "Autoruby")]
+    pub autoruby: Option<String>,
+    #[serde(rename = "True If Exists")]
+    pub true_if_exists: Option<String>,
+    // Each kanji of the field, from KANJIDIC2
+    #[serde(rename = "Kanji Breakdown")]
+    pub kanji_breakdown: Option<String>,
+    // Downstep and overline graph of the field's word
+    #[serde(rename = "Pitch Accent")]
+    pub pitch_accent: Option<String>,
+    // An Autoruby field, to pick among readings
+    #[serde(rename = "Reading")]
+    pub reading: Option<String>,
+    #[serde(rename = "Format")]
+    pub format: Option<MarkupFormat>,
+
+    // Looked up when the record leaves the field out
+    #[serde(rename = "Dictionary")]
+    pub dictionary: Option<ValueList>,
+    #[serde(rename = "Entry")]
+    pub entry: Option<DictionaryEntry>,
+    // Rank in the first frequency list that has the word
+    #[serde(rename = "Frequency")]
+    pub frequency: Option<ValueList>,
+    // N5 … N1 from the JLPT lists
+    #[serde(rename = "JLPT")]
+    pub jlpt: bool,
+    // Field to look up, the key if not given
+    #[serde(rename = "Lookup")]
+    pub lookup: Option<String>,
+    // Gloss language, `eng` if not given
+    #[serde(rename = "Language")]
+    pub language: Option<String>,
+
+    // Example sentence for another field's word, filled by `examples`
+    #[serde(rename = "Example For")]
+    pub example_for: Option<String>,
+    #[serde(rename = "Translation Of")]
+    pub translation_of: Option<String>,
+    // Anki tags, e.g. `auto-example` for picked examples
+    #[serde(rename = "Tags")]
+    pub tags: bool,
+
+    #[serde(rename = "Values")]
+    pub values: Option<ValueList>,
+    #[serde(rename = "Merge")]
+    pub merge: Option<MergePolicy>,
+    // Typed like an imported value
+    #[serde(rename = "Default")]
+    pub default: Option<JsonValue>,
+    #[serde(rename = "Now")]
+    pub now: Option<Stamp>,
+    #[serde(rename = "Source")]
+    pub source: Option<SourceStamp>,
+
+    // Validation rules
+    #[serde(rename = "Pattern")]
+    pub pattern: Option<String>,
+    #[serde(rename = "Min Length")]
+    pub min_length: Option<usize>,
+    #[serde(rename = "Max Length")]
+    pub max_length: Option<usize>,
+    #[serde(rename = "Kanji")]
+    pub kanji: bool,
+    #[serde(rename = "Kana Only")]
+    pub kana_only: bool,
+    #[serde(rename = "Contains Key")]
+    pub contains_key: bool,
+    #[serde(rename = "File Exists")]
+    pub file_exists: bool,
+    #[serde(rename = "Severity")]
+    pub severity: Option<Severity>,
 }
 
 // Card Field Spec
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct FieldSpec {
     pub name: String,
+    #[serde(default)]
     pub field_type: FieldType,
-    pub metadata: HashMap<String, String>,
+    #[serde(default)]
+    pub metadata: FieldMetadata,
 }
 
 impl FieldSpec {
@@ -28,15 +216,57 @@ impl FieldSpec {
         Alias::new(self.name.as_str())
     }
 
-    #[inline]
-    pub fn get_col(&self) -> ColumnDef {
+    // Type and CHECK constraints
+    fn typed_col(&self) -> ColumnDef {
         let mut col = match self.field_type {
             FieldType::Integer => ColumnDef::new(self.get_alias()).integer().to_owned(),
             FieldType::Text => ColumnDef::new(self.get_alias()).text().to_owned(),
             FieldType::Boolean => ColumnDef::new(self.get_alias()).boolean().to_owned(),
+            FieldType::Float => ColumnDef::new(self.get_alias()).double().to_owned(),
+            FieldType::Date => ColumnDef::new(self.get_alias()).date().to_owned(),
+            FieldType::DateTime => ColumnDef::new(self.get_alias()).date_time().to_owned(),
+            FieldType::List | FieldType::Json => ColumnDef::new(self.get_alias()).json().to_owned(),
             _ => ColumnDef::new(self.get_alias()).string().to_owned(),
         };
 
+        // Enforced by SQLite as well
+        match self.field_type {
+            FieldType::Enum if !self.enum_values().is_empty() => {
+                col.check(Expr::col(self.get_alias()).is_in(self.enum_values()));
+            }
+            FieldType::List | FieldType::Json => {
+                col.check(Expr::cust_with_exprs(
+                    "json_valid($1)",
+                    [Expr::col(self.get_alias())],
+                ));
+            }
+            _ => {}
+        }
+
+        col
+    }
+
+    // The constant `Default`, if it fits the type
+    fn default_expr(&self) -> Option<Expr> {
+        let default = self.default_value()?;
+        FieldValue::from_input(self, default)
+            .ok()
+            .map(|value| Expr::from(&value))
+    }
+
+    #[inline]
+    pub fn get_col(&self) -> ColumnDef {
+        let mut col = self.typed_col();
+
+        if let Some(default) = self.default_expr() {
+            col.default(default);
+        } else if self.now().is_some() {
+            col.default(match self.field_type {
+                FieldType::Date => Expr::current_date(),
+                _ => Expr::current_timestamp(),
+            });
+        }
+
         if (self.is_primary_key()) {
             col.primary_key();
         }
@@ -56,60 +286,460 @@ impl FieldSpec {
         col
     }
 
+    // For `ALTER TABLE … ADD COLUMN`, which takes no key, no unique constraint, no
+    // CURRENT_TIMESTAMP default and NOT NULL only with a default
+    pub fn get_added_col(&self) -> Result<ColumnDef, String> {
+        if self.is_primary_key() || self.is_auto_increment() || self.is_unique() {
+            return Err(format!(
+                "field '{}' can't be added to an existing table (key or unique); recreate the table",
+                self.name
+            ));
+        }
+
+        let mut col = self.typed_col();
+        match self.default_expr() {
+            Some(default) => {
+                col.default(default);
+                if self.is_not_null() {
+                    col.not_null();
+                }
+            }
+            None if self.is_not_null() => {
+                return Err(format!(
+                    "field '{}' is `Not Null` without a `Default`, so it can't be added to an existing table",
+                    self.name
+                ));
+            }
+            None => {}
+        }
+
+        Ok(col)
+    }
+
     #[inline]
     pub fn is_primary_key(&self) -> bool {
-        self.metadata.contains_key("Primary Key")
+        self.metadata.primary_key
     }
 
     #[inline]
     pub fn is_key(&self) -> bool {
-        self.metadata.contains_key("Key")
+        self.metadata.key
     }
 
     #[inline]
     pub fn is_auto_increment(&self) -> bool {
-        self.metadata.contains_key("Auto Increment")
+        self.metadata.auto_increment
     }
 
     #[inline]
     pub fn is_not_null(&self) -> bool {
-        self.metadata.contains_key("Not Null")
+        self.metadata.not_null
     }
 
     #[inline]
     pub fn is_unique(&self) -> bool {
-        self.metadata.contains_key("Unique")
+        self.metadata.unique
     }
 
     #[inline]
     pub fn is_image(&self) -> bool {
-        self.metadata.contains_key("Image")
+        self.metadata.image
+    }
+
+    #[inline]
+    pub fn is_audio(&self) -> bool {
+        self.metadata.audio
+    }
+
+    #[inline]
+    pub fn is_tags(&self) -> bool {
+        self.metadata.tags
     }
 
     #[inline]
     pub fn autoruby(&self) -> Option<String> {
-        self.metadata
-            .get("Autoruby")
-            .and_then(|s| s.parse::<String>().ok())
+        self.metadata.autoruby.clone()
     }
 
     #[inline]
     pub fn true_if_exists(&self) -> Option<String> {
+        self.metadata.true_if_exists.clone()
+    }
+
+    #[inline]
+    pub fn kanji_breakdown(&self) -> Option<String> {
+        self.metadata.kanji_breakdown.clone()
+    }
+
+    #[inline]
+    pub fn pitch_accent(&self) -> Option<String> {
+        self.metadata.pitch_accent.clone()
+    }
+
+    #[inline]
+    pub fn format(&self) -> MarkupFormat {
+        self.metadata.format.unwrap_or_default()
+    }
+
+    // Allowed values of an Enum field
+    #[inline]
+    pub fn enum_values(&self) -> Vec<&str> {
+        self.metadata
+            .values
+            .as_ref()
+            .map(ValueList::items)
+            .unwrap_or_default()
+    }
+
+    // Dictionaries to look the field up in, first match wins
+    #[inline]
+    pub fn dictionaries(&self) -> Vec<&str> {
+        self.metadata
+            .dictionary
+            .as_ref()
+            .map(ValueList::items)
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn is_dictionary(&self) -> bool {
+        !self.dictionaries().is_empty()
+    }
+
+    #[inline]
+    pub fn frequency_lists(&self) -> Vec<&str> {
         self.metadata
-            .get("True If Exists")
-            .and_then(|s| s.parse::<String>().ok())
+            .frequency
+            .as_ref()
+            .map(ValueList::items)
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn is_jlpt(&self) -> bool {
+        self.metadata.jlpt
+    }
+
+    // Filled from a dictionary, frequency or JLPT list when left out
+    #[inline]
+    pub fn is_looked_up(&self) -> bool {
+        self.is_dictionary() || !self.frequency_lists().is_empty() || self.is_jlpt()
+    }
+
+    // Where a looked up field comes from, for display
+    pub fn lookup_sources(&self) -> Vec<&str> {
+        let mut sources = self.dictionaries();
+        sources.extend(self.frequency_lists());
+        if self.is_jlpt() {
+            sources.push("JLPT");
+        }
+        sources
+    }
+
+    #[inline]
+    pub fn language(&self) -> &str {
+        self.metadata.language.as_deref().unwrap_or("eng")
+    }
+
+    #[inline]
+    pub fn merge_policy(&self) -> Option<MergePolicy> {
+        self.metadata.merge
+    }
+
+    // Filled by preprocess_data, never typed in by the user
+    #[inline]
+    pub fn is_derived(&self) -> bool {
+        self.autoruby().is_some()
+            || self.true_if_exists().is_some()
+            || self.kanji_breakdown().is_some()
+            || self.pitch_accent().is_some()
+    }
+
+    // Used when a record leaves the field out
+    #[inline]
+    pub fn default_value(&self) -> Option<&JsonValue> {
+        self.metadata.default.as_ref()
+    }
+
+    #[inline]
+    pub fn now(&self) -> Option<Stamp> {
+        self.metadata.now
+    }
+
+    // `Source: file`, the file a card was imported from
+    #[inline]
+    pub fn is_source(&self) -> bool {
+        self.metadata.source.is_some()
+    }
+
+    // `Source: clip`, where the example sentence is heard
+    #[inline]
+    pub fn is_clip(&self) -> bool {
+        self.metadata.source == Some(SourceStamp::Clip)
+    }
+
+    // Whether the `Clear` merge policy may empty the column
+    #[inline]
+    pub fn can_clear(&self) -> bool {
+        !self.is_primary_key() && !self.is_auto_increment() && !self.is_not_null()
+    }
+
+    // Bookkeeping stamps, not card content
+    #[inline]
+    pub fn is_auto(&self) -> bool {
+        self.now().is_some() || self.is_source()
+    }
+
+    // The fields this one is derived or looked up from
+    pub fn dependencies(&self) -> Vec<&str> {
+        [
+            &self.metadata.autoruby,
+            &self.metadata.true_if_exists,
+            &self.metadata.kanji_breakdown,
+            &self.metadata.pitch_accent,
+            &self.metadata.reading,
+            &self.metadata.lookup,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, clap::ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Tsv,
+    /// One line per card, for printing
+    Text,
+    /// Typed values, one array of objects
+    Json,
+    /// Typed values, one object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    #[inline]
+    pub fn is_json(&self) -> bool {
+        matches!(self, ExportFormat::Json | ExportFormat::Jsonl)
+    }
+
+    #[inline]
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::Text => "txt",
+            ExportFormat::Json => "json",
+            ExportFormat::Jsonl => "jsonl",
+        }
     }
+
+    pub fn from_path(filename: &str) -> Option<ExportFormat> {
+        ExportFormat::from_extension(std::path::Path::new(filename).extension()?.to_str()?)
+    }
+
+    pub fn from_extension(extension: &str) -> Option<ExportFormat> {
+        match extension {
+            "csv" => Some(ExportFormat::Csv),
+            "tsv" => Some(ExportFormat::Tsv),
+            "txt" => Some(ExportFormat::Text),
+            "json" => Some(ExportFormat::Json),
+            "jsonl" => Some(ExportFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+// Named export target, each with its own exported state per card
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportTarget {
+    pub name: String,
+    #[serde(default)]
+    pub format: ExportFormat,
+    // Empty means every field in template order
+    #[serde(default)]
+    pub fields: Vec<String>,
+    // Takes precedence over `fields`
+    pub profile: Option<String>,
+    pub file: String,
+    // Field to order the cards by, smallest first and empty last
+    #[serde(default)]
+    pub sort: Option<String>,
+    // Leave out cards ranked rarer than this in the `Frequency` field
+    #[serde(default)]
+    pub max_rank: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum RubyFormat {
+    // 漢字[かんじ], as stored
+    Anki,
+    // <ruby>漢字<rt>かんじ</rt></ruby>
+    Html,
+    // Reading only
+    Kana,
+    // Base text only
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum NewlineStyle {
+    Br,
+    Lf,
+    Space,
+    // Every line wrapped in <div>
+    Div,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum BlobFormat {
+    Base64,
+    // Written to `media_dir`, the file name is exported
+    Media,
+}
+
+// How stored values become exported text; unset options fall back to the format's
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderOptions {
+    pub strip_html: Option<bool>,
+    // Escape text that doesn't already contain HTML
+    pub escape_html: Option<bool>,
+    pub ruby: Option<RubyFormat>,
+    pub newline: Option<NewlineStyle>,
+    // Decimal places for Real values
+    pub precision: Option<usize>,
+    pub true_label: Option<String>,
+    pub false_label: Option<String>,
+    pub blob: Option<BlobFormat>,
+    pub media_dir: Option<String>,
+    // Joins List items, default ", "
+    pub separator: Option<String>,
+}
+
+impl RenderOptions {
+    pub fn merge(&self, fallback: &RenderOptions) -> RenderOptions {
+        RenderOptions {
+            strip_html: self.strip_html.or(fallback.strip_html),
+            escape_html: self.escape_html.or(fallback.escape_html),
+            ruby: self.ruby.or(fallback.ruby),
+            newline: self.newline.or(fallback.newline),
+            precision: self.precision.or(fallback.precision),
+            true_label: self.true_label.clone().or(fallback.true_label.clone()),
+            false_label: self.false_label.clone().or(fallback.false_label.clone()),
+            blob: self.blob.or(fallback.blob),
+            media_dir: self.media_dir.clone().or(fallback.media_dir.clone()),
+            separator: self.separator.clone().or(fallback.separator.clone()),
+        }
+    }
+}
+
+// One output column of an export profile, with the render options of `RenderOptions`
+// spelled out so unknown keys are still rejected
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileField {
+    pub field: String,
+    // Output column name, defaults to the field name
+    pub column: Option<String>,
+    pub strip_html: Option<bool>,
+    pub escape_html: Option<bool>,
+    pub ruby: Option<RubyFormat>,
+    pub newline: Option<NewlineStyle>,
+    pub precision: Option<usize>,
+    pub true_label: Option<String>,
+    pub false_label: Option<String>,
+    pub blob: Option<BlobFormat>,
+    pub media_dir: Option<String>,
+    pub separator: Option<String>,
+}
+
+impl ProfileField {
+    pub fn new(field: &str) -> Self {
+        ProfileField {
+            field: field.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn render(&self) -> RenderOptions {
+        RenderOptions {
+            strip_html: self.strip_html,
+            escape_html: self.escape_html,
+            ruby: self.ruby,
+            newline: self.newline,
+            precision: self.precision,
+            true_label: self.true_label.clone(),
+            false_label: self.false_label.clone(),
+            blob: self.blob,
+            media_dir: self.media_dir.clone(),
+            separator: self.separator.clone(),
+        }
+    }
+
+    #[inline]
+    pub fn get_column(&self) -> &str {
+        self.column.as_deref().unwrap_or(&self.field)
+    }
+}
+
+// Which fields to emit, in which order and under which column names
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportProfile {
+    pub name: String,
+    pub fields: Vec<ProfileField>,
+    // Write the column names as the first row
+    #[serde(default)]
+    pub header: bool,
+}
+
+impl ExportProfile {
+    pub fn get_header(&self) -> Option<Vec<String>> {
+        if !self.header {
+            return None;
+        }
+
+        Some(
+            self.fields
+                .iter()
+                .map(|field| field.get_column().to_string())
+                .collect(),
+        )
+    }
+}
+
+// Template schema version this build reads
+pub const TEMPLATE_VERSION: u32 = 1;
+
+#[inline]
+fn default_version() -> u32 {
+    TEMPLATE_VERSION
 }
 
 // Card MetaData
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CardMetadata {
+    #[serde(default = "default_version")]
+    pub version: u32,
     pub name: String,
     pub fields: Vec<FieldSpec>,
+    #[serde(default)]
+    pub targets: Vec<ExportTarget>,
+    #[serde(default)]
+    pub profiles: Vec<ExportProfile>,
+    // Render defaults per export format
+    #[serde(default)]
+    pub render: HashMap<ExportFormat, RenderOptions>,
 }
 
 impl CardMetadata {
-    // Helper Fn
+    // Helper Fn; from_yaml made sure there is exactly one
     pub fn get_main_key(&self) -> &FieldSpec {
         self.fields
             .iter()
@@ -118,55 +748,226 @@ impl CardMetadata {
             .unwrap()
     }
 
+    // Per field: import override, then template `Merge`, then the import default
+    pub fn merge_policies(
+        &self,
+        default: MergePolicy,
+        overrides: &[(String, MergePolicy)],
+    ) -> Result<HashMap<String, MergePolicy>, String> {
+        for (name, policy) in overrides {
+            match self.fields.iter().find(|field| field.name == *name) {
+                None => return Err(format!("unknown field '{}'", name)),
+                Some(field) if *policy == MergePolicy::Clear && !field.can_clear() => {
+                    return Err(format!("field '{}' can't be cleared", name));
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(self
+            .fields
+            .iter()
+            .map(|field| {
+                // A global `clear` leaves ids and required fields alone
+                let default = match default {
+                    MergePolicy::Clear if !field.can_clear() => MergePolicy::Overwrite,
+                    default => default,
+                };
+                let policy = overrides
+                    .iter()
+                    .rev()
+                    .find(|(name, _)| *name == field.name)
+                    .map(|(_, policy)| *policy)
+                    .or(field.merge_policy())
+                    .unwrap_or(default);
+                (field.name.clone(), policy)
+            })
+            .collect())
+    }
+
+    // Defaults and stamps for the fields a record leaves out, with the policy each merges by
+    pub fn fill_record(
+        &self,
+        record: &Record,
+        source: Option<&str>,
+        now: &str,
+    ) -> (Record, HashMap<String, MergePolicy>) {
+        let mut record = record.clone();
+        let mut policies = HashMap::new();
+
+        for field in &self.fields {
+            // Looked up in preprocess_data, never over a stored value
+            if field.is_looked_up() && field.merge_policy().is_none() {
+                match record.get(&field.name) {
+                    None => {}
+                    Some(JsonValue::String(s)) if s.trim().is_empty() => {}
+                    Some(_) => continue,
+                }
+                policies.insert(field.name.clone(), MergePolicy::Fill);
+                continue;
+            }
+
+            if record.contains_key(&field.name) {
+                continue;
+            }
+
+            // A Date only takes the day
+            let stamp = match field.field_type {
+                FieldType::Date => now.get(..10).unwrap_or(now),
+                _ => now,
+            };
+            let (value, policy) = match (field.default_value(), field.now(), source) {
+                (Some(default), _, _) => (default.clone(), MergePolicy::Fill),
+                (None, Some(Stamp::Created), _) => (stamp.into(), MergePolicy::Fill),
+                (None, Some(Stamp::Updated), _) => (stamp.into(), MergePolicy::Touch),
+                (None, None, Some(source)) if field.is_source() && !field.is_clip() => {
+                    (source.into(), MergePolicy::Fill)
+                }
+                _ => continue,
+            };
+
+            record.insert(field.name.clone(), value);
+            // `Touch` always, the rest unless the template says otherwise
+            if policy == MergePolicy::Touch || field.merge_policy().is_none() {
+                policies.insert(field.name.clone(), policy);
+            }
+        }
+
+        (record, policies)
+    }
+
+    // The field ranked by `Frequency`, for `max_rank`
+    pub fn get_frequency_field(&self) -> Option<&FieldSpec> {
+        self.fields
+            .iter()
+            .find(|field| !field.frequency_lists().is_empty())
+    }
+
+    // `sort` and `max_rank` of a target, also when given on the command line
+    pub fn check_target_order(&self, target: &ExportTarget) -> Result<(), String> {
+        if let Some(sort) = &target.sort {
+            if !self.fields.iter().any(|field| field.name == *sort) {
+                return Err(format!(
+                    "target '{}': can't sort by unknown field '{}'",
+                    target.name, sort
+                ));
+            }
+        }
+        if target.max_rank.is_some() && self.get_frequency_field().is_none() {
+            return Err(format!(
+                "target '{}': `max_rank` needs a field with `Frequency`",
+                target.name
+            ));
+        }
+        Ok(())
+    }
+
+    // Where media files live: the first `media_dir` under `render:`, `media` otherwise
+    pub fn media_dir(&self) -> &str {
+        [
+            ExportFormat::Csv,
+            ExportFormat::Tsv,
+            ExportFormat::Text,
+            ExportFormat::Json,
+            ExportFormat::Jsonl,
+        ]
+        .iter()
+        .find_map(|format| self.render.get(format)?.media_dir.as_deref())
+        .unwrap_or("media")
+    }
+
+    pub fn get_target(&self, name: &str) -> Option<&ExportTarget> {
+        self.targets.iter().find(|target| target.name == name)
+    }
+
+    // The target's profile, or a plain one from its field list (every field if empty)
+    pub fn get_export_profile(&self, target: &ExportTarget) -> Result<ExportProfile, String> {
+        let profile = match &target.profile {
+            Some(name) => self
+                .profiles
+                .iter()
+                .find(|profile| profile.name == *name)
+                .cloned()
+                .ok_or_else(|| format!("target '{}': unknown profile '{}'", target.name, name))?,
+            None if target.fields.is_empty() => ExportProfile {
+                name: target.name.clone(),
+                fields: self
+                    .fields
+                    .iter()
+                    .map(|field| ProfileField::new(&field.name))
+                    .collect(),
+                header: false,
+            },
+            None => ExportProfile {
+                name: target.name.clone(),
+                fields: target
+                    .fields
+                    .iter()
+                    .map(|name| ProfileField::new(name))
+                    .collect(),
+                header: false,
+            },
+        };
+
+        for column in &profile.fields {
+            if !self.fields.iter().any(|field| field.name == column.field) {
+                return Err(format!(
+                    "profile '{}': unknown field '{}'",
+                    profile.name, column.field
+                ));
+            }
+        }
+
+        Ok(profile)
+    }
+
+    // Typed values for the fields present in the record; `null` clears a field
     pub fn get_data_from_record(
         &self,
-        record: &HashMap<String, String>,
-    ) -> Option<(Vec<Alias>, Vec<String>, Alias)> {
+        record: &Record,
+    ) -> Result<(Vec<Alias>, Vec<FieldValue>, Alias), String> {
         // Early return statement
         if self.fields.is_empty() {
-            return None;
+            return Err("template has no fields".to_string());
         }
 
         let mut aliases = Vec::new();
         let mut values = Vec::new();
         let mut key: Option<Alias> = None;
         for field in &self.fields {
-            // The data that have be inputted
-            if field.is_not_null() && !field.is_auto_increment() {
-                match record.get(&field.name) {
-                    Some(val) => {
-                        if field.is_key() {
-                            key = Some(field.get_alias())
-                        }
-                        aliases.push(field.get_alias());
-                        values.push(val.clone());
-                    }
-                    _ => return None, // missing or empty → invalid
-                }
-            } else {
-                if let Some(val) = record.get(&field.name) {
-                    if field.is_key() {
-                        key = Some(field.get_alias())
-                    }
-                    aliases.push(field.get_alias());
-                    values.push(val.clone());
+            let value = match record.get(&field.name) {
+                Some(val) => FieldValue::from_input(field, val).map_err(|e| e.to_string())?,
+                // The data that have be inputted
+                None if field.is_not_null() && !field.is_auto_increment() => {
+                    return Err(format!("missing required field '{}'", field.name));
                 }
+                None => continue,
+            };
+
+            // Clearing a required field would break the row
+            if value.is_null() && field.is_not_null() {
+                return Err(format!("field '{}' can't be cleared", field.name));
             }
-        }
 
-        // case: all fields nullable → require at least one alias
-        if aliases.is_empty() && key.is_some() {
-            return None;
+            if field.is_key() {
+                key = Some(field.get_alias())
+            }
+            aliases.push(field.get_alias());
+            values.push(value);
         }
 
-        Some((aliases, values, key.unwrap()))
+        match key {
+            Some(key) if !aliases.is_empty() => Ok((aliases, values, key)),
+            _ => Err(format!("missing key field '{}'", self.get_main_key().name)),
+        }
     }
     // Preprocess the get_data_from_record
     pub fn preprocess_data(
         &self,
+        conn: &rusqlite::Connection,
         fields: &Vec<Alias>,
-        values: &Vec<String>,
-    ) -> Option<(Vec<Alias>, Vec<String>)> {
+        values: &Vec<FieldValue>,
+    ) -> Option<(Vec<Alias>, Vec<FieldValue>)> {
         // Early return statement
         if self.fields.is_empty() {
             return None;
@@ -176,43 +977,114 @@ impl CardMetadata {
         let mut values = values.clone();
 
         for field in &self.fields {
-            if aliases.contains(&field.get_alias()) && !field.is_image() {
-                continue;
-            } else if !aliases.contains(&field.get_alias()) && field.is_image() {
-                continue;
-            }
-
             let pos = aliases
                 .iter()
                 .position(|value| value.0 == field.get_alias().0);
 
+            // Given by the user; a cleared derived field is computed again
+            match pos {
+                Some(pos) if field.is_image() => {
+                    if let FieldValue::Text(src) = &values[pos] {
+                        values[pos] = FieldValue::Text(format!("<img src=\"{}\">", src));
+                    }
+                    continue;
+                }
+                None if field.is_image() => continue,
+                // Looked up only when left out or blank; `null` still clears
+                Some(pos)
+                    if field.is_looked_up()
+                        && (values[pos].is_null() || !values[pos].is_empty()) =>
+                {
+                    continue;
+                }
+                _ if field.is_looked_up() => {}
+                Some(pos) if !values[pos].is_null() || !field.is_derived() => continue,
+                _ => {}
+            }
+
             // The input is not filled or missing
-            if field.is_image() {
-                values[pos.unwrap()] = format!("<img src=\"{}\">", values[pos.unwrap()]);
-            } else if let Some(target) = field.autoruby() {
+            let computed = if let Some(target) = field.autoruby() {
                 let target_pos = aliases.iter().position(|value| value.0 == target);
-                match target_pos {
-                    None => continue,
-                    Some(target_pos) => {
-                        let result = annotate(values[target_pos].as_str());
-                        if result == values[target_pos] {
+                match target_pos.map(|target_pos| &values[target_pos]) {
+                    Some(FieldValue::Text(text)) => {
+                        let result = annotate(text);
+                        if result == *text {
                             continue;
                         }
-
-                        aliases.push(field.get_alias());
-                        values.push(result);
+                        FieldValue::Text(result)
                     }
+                    Some(FieldValue::Null) => FieldValue::Null,
+                    _ => continue,
                 }
             } else if let Some(target) = field.true_if_exists() {
                 let target_pos = aliases.iter().position(|value| value.0 == target);
-                match target_pos {
-                    None => continue,
-                    Some(target_pos) => {
-                        if (!values[target_pos].is_empty()) {
-                            aliases.push(field.get_alias());
-                            values.push("1".to_string());
+                match target_pos.map(|target_pos| &values[target_pos]) {
+                    Some(FieldValue::Null) => FieldValue::Null,
+                    Some(value) if !value.is_empty() => FieldValue::Boolean(true),
+                    _ => continue,
+                }
+            } else if let Some(target) = field.kanji_breakdown() {
+                let target_pos = aliases.iter().position(|value| value.0 == target);
+                match target_pos.map(|target_pos| &values[target_pos]) {
+                    Some(FieldValue::Text(text)) => {
+                        let mut characters: Vec<kanjidic::Kanji> = Vec::new();
+                        for c in text.chars().filter(|c| validate::is_kanji(*c)) {
+                            if characters.iter().any(|kanji| kanji.literal.starts_with(c)) {
+                                continue;
+                            }
+                            match kanjidic::lookup(conn, c) {
+                                Ok(Some(kanji)) => characters.push(kanji),
+                                Ok(None) => {}
+                                Err(e) => eprintln!("⚠️ {}: kanji lookup failed: {}", c, e),
+                            }
+                        }
+                        if characters.is_empty() {
+                            continue;
+                        }
+                        FieldValue::Text(kanjidic::breakdown(&characters, field.format()))
+                    }
+                    Some(FieldValue::Null) => FieldValue::Null,
+                    _ => continue,
+                }
+            } else if let Some(target) = field.pitch_accent() {
+                let target_pos = aliases.iter().position(|value| value.0 == target);
+                match target_pos.map(|target_pos| &values[target_pos]) {
+                    Some(FieldValue::Text(word)) => {
+                        let reading = field.metadata.reading.as_ref().and_then(|reading| {
+                            let pos = aliases.iter().position(|value| value.0 == *reading)?;
+                            match &values[pos] {
+                                FieldValue::Text(text) => Some(pitch::reading_of(text)),
+                                _ => None,
+                            }
+                        });
+                        match pitch::lookup(conn, word, reading.as_deref()) {
+                            Ok(Some((reading, accents))) => {
+                                FieldValue::Text(pitch::render(&reading, &accents, field.format()))
+                            }
+                            Ok(None) => continue,
+                            Err(e) => {
+                                eprintln!("⚠️ {}: pitch accent lookup failed: {}", word, e);
+                                continue;
+                            }
                         }
                     }
+                    Some(FieldValue::Null) => FieldValue::Null,
+                    _ => continue,
+                }
+            } else if field.is_looked_up() {
+                match self.look_up(conn, field, &aliases, &values) {
+                    Some(value) => value,
+                    None => continue,
+                }
+            } else {
+                continue;
+            };
+
+            match pos {
+                Some(pos) => values[pos] = computed,
+                None => {
+                    aliases.push(field.get_alias());
+                    values.push(computed);
                 }
             }
         }
@@ -225,10 +1097,352 @@ impl CardMetadata {
         Some((aliases, values))
     }
 
+    // Only the key, the changed fields and what is derived from them, for updating stored cards
+    pub fn only_changed(
+        &self,
+        changed: &[&str],
+        fields: Vec<Alias>,
+        values: Vec<FieldValue>,
+    ) -> (Vec<Alias>, Vec<FieldValue>) {
+        let key = self.get_main_key();
+        fields
+            .into_iter()
+            .zip(values)
+            .filter(|(alias, _)| {
+                alias.0 == key.name
+                    || changed.contains(&alias.0.as_str())
+                    || self.fields.iter().any(|field| {
+                        field.name == alias.0
+                            && field.dependencies().iter().any(|dep| changed.contains(dep))
+                    })
+            })
+            .unzip()
+    }
+
+    // The field's value from the first dictionary or list that has its lookup field
+    fn look_up(
+        &self,
+        conn: &rusqlite::Connection,
+        field: &FieldSpec,
+        aliases: &[Alias],
+        values: &[FieldValue],
+    ) -> Option<FieldValue> {
+        let source = match &field.metadata.lookup {
+            Some(source) => source.clone(),
+            None => self.get_main_key().name.clone(),
+        };
+        let pos = aliases.iter().position(|alias| alias.0 == source)?;
+        let FieldValue::Text(word) = &values[pos] else {
+            return None;
+        };
+
+        for name in field.dictionaries() {
+            match dictionary::lookup(conn, name, word, field.language()) {
+                Ok(Some(entry)) => {
+                    let value = dictionary::entry_value(field, &entry)?;
+                    return FieldValue::from_input(field, &value).ok();
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("⚠️ {}: {} lookup failed: {}", word, name, e),
+            }
+        }
+
+        for list in field.frequency_lists() {
+            match frequency::rank(conn, list, word) {
+                Ok(Some(rank)) => return Some(FieldValue::Integer(rank)),
+                Ok(None) => {}
+                Err(e) => eprintln!("⚠️ {}: {} lookup failed: {}", word, list, e),
+            }
+        }
+
+        if field.is_jlpt() {
+            match frequency::jlpt(conn, word) {
+                Ok(Some(level)) => {
+                    let value = match field.field_type {
+                        FieldType::Integer => JsonValue::from(level),
+                        _ => JsonValue::from(format!("N{level}")),
+                    };
+                    return FieldValue::from_input(field, &value).ok();
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("⚠️ {}: JLPT lookup failed: {}", word, e),
+            }
+        }
+
+        None
+    }
+
+    // Errors across fields, targets and profiles; empty if the template is usable
+    pub fn check(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.version > TEMPLATE_VERSION {
+            errors.push(format!(
+                "template version {} is newer than supported ({})",
+                self.version, TEMPLATE_VERSION
+            ));
+        }
+
+        if self.fields.iter().filter(|field| field.is_tags()).count() > 1 {
+            errors.push("more than one field is marked as `Tags`".to_string());
+        }
+        if self
+            .fields
+            .iter()
+            .filter(|field| field.metadata.example_for.is_some())
+            .count()
+            > 1
+        {
+            errors.push("more than one field has `Example For`".to_string());
+        }
+        if self.fields.iter().filter(|field| field.is_clip()).count() > 1 {
+            errors.push("more than one field has `Source: clip`".to_string());
+        }
+
+        match self.fields.iter().filter(|field| field.is_key()).count() {
+            0 => errors.push("no field is marked as `Key`".to_string()),
+            1 => {}
+            _ => errors.push("more than one field is marked as `Key`".to_string()),
+        }
+
+        for (i, field) in self.fields.iter().enumerate() {
+            if self.fields[..i]
+                .iter()
+                .any(|other| other.name == field.name)
+            {
+                errors.push(format!("field '{}' is defined twice", field.name));
+            }
+
+            for target in field.dependencies() {
+                let source = self.fields.iter().position(|other| other.name == target);
+                match source {
+                    _ if target == field.name => {
+                        errors.push(format!("field '{}' is derived from itself", field.name))
+                    }
+                    None => errors.push(format!(
+                        "field '{}' is derived from unknown field '{}'",
+                        field.name, target
+                    )),
+                    // preprocess_data goes in template order
+                    Some(source)
+                        if source > i
+                            && (self.fields[source].is_derived()
+                                || self.fields[source].is_looked_up()) =>
+                    {
+                        errors.push(format!(
+                            "field '{}' is derived from '{}', which is computed after it",
+                            field.name, target
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            match (&field.field_type, field.enum_values().is_empty()) {
+                (FieldType::Enum, true) => {
+                    errors.push(format!("Enum field '{}' has no `Values`", field.name))
+                }
+                (FieldType::Enum, false) => {}
+                (_, false) => errors.push(format!(
+                    "field '{}' has `Values` but isn't an Enum",
+                    field.name
+                )),
+                _ => {}
+            }
+
+            let meta = &field.metadata;
+            if !field.is_dictionary() && (meta.entry.is_some() || meta.language.is_some()) {
+                errors.push(format!(
+                    "field '{}' has dictionary options but no `Dictionary`",
+                    field.name
+                ));
+            }
+            if !field.is_looked_up() && meta.lookup.is_some() {
+                errors.push(format!(
+                    "field '{}' has a `Lookup` but nothing to look up",
+                    field.name
+                ));
+            }
+            let sources = [
+                field.is_dictionary(),
+                !field.frequency_lists().is_empty(),
+                field.is_jlpt(),
+            ];
+            if sources.iter().filter(|set| **set).count() > 1 {
+                errors.push(format!(
+                    "field '{}' can only have one of `Dictionary`, `Frequency` and `JLPT`",
+                    field.name
+                ));
+            }
+            if !field.frequency_lists().is_empty()
+                && !matches!(field.field_type, FieldType::Integer)
+            {
+                errors.push(format!(
+                    "field '{}': `Frequency` needs an Integer field",
+                    field.name
+                ));
+            }
+            if field.is_jlpt()
+                && !matches!(
+                    field.field_type,
+                    FieldType::String | FieldType::Enum | FieldType::Integer
+                )
+            {
+                errors.push(format!(
+                    "field '{}': `JLPT` needs a String, Enum or Integer field",
+                    field.name
+                ));
+            }
+            let markup = field.kanji_breakdown().is_some() || field.pitch_accent().is_some();
+            if markup && !matches!(field.field_type, FieldType::String | FieldType::Text) {
+                errors.push(format!(
+                    "field '{}': `Kanji Breakdown` and `Pitch Accent` need a String or Text field",
+                    field.name
+                ));
+            }
+            if let Some(source) = &meta.example_for {
+                if !self.fields.iter().any(|other| other.name == *source) {
+                    errors.push(format!(
+                        "field '{}' is an example for unknown field '{}'",
+                        field.name, source
+                    ));
+                }
+            }
+            if let Some(sentence) = &meta.translation_of {
+                let example = self.fields.iter().find(|other| other.name == *sentence);
+                if !example.is_some_and(|other| other.metadata.example_for.is_some()) {
+                    errors.push(format!(
+                        "field '{}' translates '{}', which has no `Example For`",
+                        field.name, sentence
+                    ));
+                }
+            }
+            if field.is_tags()
+                && !matches!(
+                    field.field_type,
+                    FieldType::String | FieldType::Text | FieldType::List
+                )
+            {
+                errors.push(format!(
+                    "field '{}': `Tags` needs a String, Text or List field",
+                    field.name
+                ));
+            }
+            if field.now().is_some()
+                && !matches!(
+                    field.field_type,
+                    FieldType::Date | FieldType::DateTime | FieldType::String | FieldType::Text
+                )
+            {
+                errors.push(format!(
+                    "field '{}': `Now` needs a Date, DateTime, String or Text field",
+                    field.name
+                ));
+            }
+            if field.merge_policy() == Some(MergePolicy::Clear) && !field.can_clear() {
+                errors.push(format!(
+                    "field '{}': a key, id or `Not Null` field can't be `Merge: Clear`",
+                    field.name
+                ));
+            }
+            if field.is_clip() && !matches!(field.field_type, FieldType::String | FieldType::Text) {
+                errors.push(format!(
+                    "field '{}': `Source: clip` needs a String or Text field",
+                    field.name
+                ));
+            }
+            if meta.reading.is_some() && field.pitch_accent().is_none() {
+                errors.push(format!(
+                    "field '{}' has a `Reading` but no `Pitch Accent`",
+                    field.name
+                ));
+            }
+            if meta.format.is_some() && !markup {
+                errors.push(format!(
+                    "field '{}' has a `Format` but nothing to format",
+                    field.name
+                ));
+            }
+            if field.is_looked_up() && field.is_derived() {
+                errors.push(format!(
+                    "field '{}' is both derived and looked up",
+                    field.name
+                ));
+            }
+            match (meta.entry.unwrap_or_default(), &field.field_type) {
+                (_, _) if !field.is_dictionary() => {}
+                (DictionaryEntry::Gloss, FieldType::String | FieldType::Text) => {}
+                (DictionaryEntry::Pos, FieldType::List | FieldType::String | FieldType::Text) => {}
+                (DictionaryEntry::Common, FieldType::Boolean) => {}
+                (entry, field_type) => errors.push(format!(
+                    "field '{}': `Entry: {:?}` can't fill a {:?} field",
+                    field.name, entry, field_type
+                )),
+            }
+
+            if let Some(default) = field.default_value() {
+                if let Err(e) = FieldValue::from_input(field, default) {
+                    errors.push(format!("default of {}", e));
+                }
+            }
+
+            if field.is_auto() && field.default_value().is_some() {
+                errors.push(format!(
+                    "field '{}' has both a stamp and a `Default`",
+                    field.name
+                ));
+            }
+        }
+
+        if let Err(e) = Validator::new(self) {
+            errors.push(e);
+        }
+
+        for (i, target) in self.targets.iter().enumerate() {
+            if self.targets[..i]
+                .iter()
+                .any(|other| other.name == target.name)
+            {
+                errors.push(format!("target '{}' is defined twice", target.name));
+            }
+            if let Err(e) = self.get_export_profile(target) {
+                errors.push(e);
+            }
+            if let Err(e) = self.check_target_order(target) {
+                errors.push(e);
+            }
+        }
+
+        for profile in &self.profiles {
+            for column in &profile.fields {
+                if !self.fields.iter().any(|field| field.name == column.field) {
+                    errors.push(format!(
+                        "profile '{}': unknown field '{}'",
+                        profile.name, column.field
+                    ));
+                }
+            }
+        }
+
+        errors
+    }
+
     pub fn from_yaml(filename: &str) -> Result<CardMetadata, Box<dyn Error>> {
-        let file_content = std::fs::read_to_string(filename)?;
-        let card_metadata: CardMetadata = serde_yaml::from_str(&file_content)?;
+        let card_metadata = CardMetadata::parse_yaml(filename)?;
 
+        let errors = card_metadata.check();
+        if !errors.is_empty() {
+            return Err(format!("{filename}:\n  {}", errors.join("\n  ")).into());
+        }
+
+        Ok(card_metadata)
+    }
+
+    // Only the YAML, for `template check` to list the errors itself
+    pub fn parse_yaml(filename: &str) -> Result<CardMetadata, Box<dyn Error>> {
+        let file_content = std::fs::read_to_string(filename)?;
+        let card_metadata: CardMetadata =
+            serde_yaml::from_str(&file_content).map_err(|e| format!("{filename}: {e}"))?;
         Ok(card_metadata)
     }
 }
@@ -0,0 +1,230 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use rusqlite::{Connection, params};
+use sea_query::{Alias, ColumnDef, Expr, ExprTrait, Index, Query, SqliteQueryBuilder, Table};
+use sea_query_rusqlite::RusqliteBinder;
+
+use crate::{dedupe::normalize, models::MarkupFormat, render::escape_html};
+
+// `base[reading]` as written by the Autoruby field
+static RUBY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([^\s\[\]]+)\[([^\]]+)\]").expect("valid regex"));
+
+pub fn create_pitch_sql() -> Vec<String> {
+    let table = Table::create()
+        .table(Alias::new("dict_pitch"))
+        .if_not_exists()
+        .col(ColumnDef::new(Alias::new("expression")).string().not_null())
+        .col(ColumnDef::new(Alias::new("reading")).string().not_null())
+        // JSON array of downstep positions, 0 for heiban
+        .col(ColumnDef::new(Alias::new("accents")).json().not_null())
+        .to_owned();
+
+    let index = Index::create()
+        .if_not_exists()
+        .name("dict_pitch_expression")
+        .table(Alias::new("dict_pitch"))
+        .col(Alias::new("expression"))
+        .to_owned();
+
+    vec![
+        table.build(SqliteQueryBuilder),
+        index.build(SqliteQueryBuilder),
+    ]
+}
+
+// Kanjium accents.txt: expression, reading (blank if the same), accents like `0` or `1,(名)0`
+pub fn read_tsv(
+    filename: &str,
+) -> Result<Vec<(String, String, Vec<u32>)>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+
+    let mut rows = Vec::new();
+    for line in content.lines() {
+        let mut columns = line.split('\t');
+        let (Some(expression), Some(reading), Some(accents)) =
+            (columns.next(), columns.next(), columns.next())
+        else {
+            continue;
+        };
+
+        // Part of speech notes in parentheses are dropped
+        let accents: Vec<u32> = accents
+            .split(',')
+            .filter_map(|accent| {
+                let digits: String = accent.chars().filter(char::is_ascii_digit).collect();
+                digits.parse().ok()
+            })
+            .collect();
+        if expression.is_empty() || accents.is_empty() {
+            continue;
+        }
+
+        let reading = match reading.is_empty() {
+            true => expression,
+            false => reading,
+        };
+        rows.push((expression.to_string(), normalize(reading), accents));
+    }
+
+    Ok(rows)
+}
+
+// Replaces the stored accents
+pub fn store(
+    conn: &mut Connection,
+    rows: &[(String, String, Vec<u32>)],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let tx = conn.transaction()?;
+
+    let (sql, values) = Query::delete()
+        .from_table(Alias::new("dict_pitch"))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+    tx.execute(&sql, &*values.as_params())?;
+
+    let (insert, _) = Query::insert()
+        .into_table(Alias::new("dict_pitch"))
+        .columns(["expression", "reading", "accents"].map(Alias::new))
+        .values_panic([(); 3].map(|_| Expr::value("")))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    {
+        let mut stmt = tx.prepare_cached(&insert)?;
+        for (expression, reading, accents) in rows {
+            stmt.execute(params![
+                expression,
+                reading,
+                serde_json::to_string(accents)?
+            ])?;
+        }
+    }
+
+    tx.commit()?;
+    Ok(rows.len())
+}
+
+// Reading and accents of a word; with a known reading only a matching row counts
+pub fn lookup(
+    conn: &Connection,
+    word: &str,
+    reading: Option<&str>,
+) -> rusqlite::Result<Option<(String, Vec<u32>)>> {
+    let (sql, values) = Query::select()
+        .columns(["reading", "accents"].map(Alias::new))
+        .from(Alias::new("dict_pitch"))
+        .and_where(Expr::col(Alias::new("expression")).eq(word))
+        .to_owned()
+        .build_rusqlite(SqliteQueryBuilder);
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(&*values.as_params(), |row| {
+            let accents: String = row.get(1)?;
+            Ok((
+                row.get::<_, String>(0)?,
+                serde_json::from_str::<Vec<u32>>(&accents).unwrap_or_default(),
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let reading = reading.map(normalize);
+    Ok(rows.into_iter().find(|(row_reading, _)| {
+        reading
+            .as_ref()
+            .is_none_or(|reading| reading == row_reading)
+    }))
+}
+
+// 日本[にほん] 語[ご] → にほんご
+pub fn reading_of(annotated: &str) -> String {
+    RUBY.replace_all(annotated, "$2")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+// Small kana belong to the mora before them
+fn morae(reading: &str) -> Vec<String> {
+    let mut morae: Vec<String> = Vec::new();
+    for c in reading.chars() {
+        match morae.last_mut() {
+            Some(mora) if "ゃゅょぁぃぅぇぉゎャュョァィゥェォヮ".contains(c) => {
+                mora.push(c)
+            }
+            _ => morae.push(c.to_string()),
+        }
+    }
+    morae
+}
+
+// High or low per mora for a downstep position
+fn is_high(mora: usize, accent: usize) -> bool {
+    match accent {
+        // Heiban: low, then high
+        0 => mora > 0,
+        // Atamadaka: high, then low
+        1 => mora == 0,
+        // Nakadaka / odaka: low, high up to the downstep
+        _ => mora > 0 && mora < accent,
+    }
+}
+
+// Downstep numbers plus an overline graph (HTML) or ꜜ marks (text)
+pub fn render(reading: &str, accents: &[u32], format: MarkupFormat) -> String {
+    let morae = morae(reading);
+
+    let patterns: Vec<String> = accents
+        .iter()
+        .map(|&accent| {
+            let accent = accent as usize;
+            match format {
+                MarkupFormat::Text => {
+                    let mut text = String::new();
+                    for (i, mora) in morae.iter().enumerate() {
+                        text.push_str(mora);
+                        if accent > 0 && i + 1 == accent && i + 1 < morae.len() {
+                            text.push('ꜜ');
+                        }
+                    }
+                    format!("{} [{}]", text, accent)
+                }
+                MarkupFormat::Html => {
+                    let graph: String = morae
+                        .iter()
+                        .enumerate()
+                        .map(|(i, mora)| {
+                            let mut style = Vec::new();
+                            if is_high(i, accent) {
+                                style.push("border-top: 1px solid currentColor");
+                            }
+                            // The drop, also after the last mora for odaka
+                            if accent > 0 && i + 1 == accent {
+                                style.push("border-right: 1px solid currentColor");
+                            }
+                            match style.is_empty() {
+                                true => format!("<span>{}</span>", escape_html(mora)),
+                                false => format!(
+                                    "<span style=\"{}\">{}</span>",
+                                    style.join("; "),
+                                    escape_html(mora)
+                                ),
+                            }
+                        })
+                        .collect();
+                    format!(
+                        "<span class=\"pitch\">{}</span> <span class=\"pitch-number\">[{}]</span>",
+                        graph, accent
+                    )
+                }
+            }
+        })
+        .collect();
+
+    match format {
+        MarkupFormat::Text => patterns.join(", "),
+        MarkupFormat::Html => patterns.join("<br>"),
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::Path;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rusqlite::types::Value;
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    models::{BlobFormat, FieldType, NewlineStyle, RenderOptions, RubyFormat},
+    validate::{is_kana, is_kanji},
+};
+
+// Stored value to exported text
+pub fn render_value(
+    value: &Value,
+    field_type: &FieldType,
+    options: &RenderOptions,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::Integer(i) if matches!(field_type, FieldType::Boolean) => {
+            let label = if *i != 0 {
+                &options.true_label
+            } else {
+                &options.false_label
+            };
+            label.clone().unwrap_or_else(|| i.to_string())
+        }
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => match options.precision {
+            Some(precision) => format!("{f:.precision$}"),
+            None => f.to_string(),
+        },
+        Value::Text(t) if matches!(field_type, FieldType::List) => {
+            render_text(&join_list(t, options), options)
+        }
+        Value::Text(t) if matches!(field_type, FieldType::Json) => t.clone(),
+        Value::Text(t) => render_text(t, options),
+        Value::Blob(b) => render_blob(b, options)?,
+    };
+
+    Ok(text)
+}
+
+// Stored value as plain text, no rendering
+pub fn plain(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(t) => t.clone(),
+        Value::Blob(b) => STANDARD.encode(b),
+    }
+}
+
+// Items of a stored JSON array as one line of text
+fn join_list(text: &str, options: &RenderOptions) -> String {
+    let Ok(JsonValue::Array(items)) = serde_json::from_str(text) else {
+        return text.to_string();
+    };
+
+    items
+        .iter()
+        .map(|item| match item {
+            JsonValue::String(s) => s.clone(),
+            item => item.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(options.separator.as_deref().unwrap_or(", "))
+}
+
+// strip HTML → escape → ruby → newlines, so generated tags survive
+fn render_text(text: &str, options: &RenderOptions) -> String {
+    let mut text = text.to_string();
+    if options.strip_html == Some(true) {
+        text = strip_html(&text);
+    }
+    if options.escape_html == Some(true) && !looks_like_html(&text) {
+        text = escape_html(&text);
+    }
+    if let Some(format) = options.ruby {
+        text = ruby(&text, format);
+    }
+    newline(&text, options.newline.unwrap_or(NewlineStyle::Br))
+}
+
+fn render_blob(blob: &[u8], options: &RenderOptions) -> Result<String, Box<dyn std::error::Error>> {
+    match options.blob.unwrap_or(BlobFormat::Base64) {
+        BlobFormat::Base64 => Ok(STANDARD.encode(blob)),
+        BlobFormat::Media => {
+            let dir = Path::new(options.media_dir.as_deref().unwrap_or("media"));
+            Ok(store_media(dir, blob, media_extension(blob))?)
+        }
+    }
+}
+
+// Named by content, so exporting twice doesn't duplicate files
+pub fn store_media(dir: &Path, blob: &[u8], extension: &str) -> std::io::Result<String> {
+    std::fs::create_dir_all(dir)?;
+
+    let hash = format!("{:x}", Sha256::digest(blob));
+    let name = format!("{}.{}", &hash[..16], extension);
+    let path = dir.join(&name);
+    if !path.exists() {
+        std::fs::write(path, blob)?;
+    }
+    Ok(name)
+}
+
+fn media_extension(blob: &[u8]) -> &'static str {
+    match blob {
+        [0x89, b'P', b'N', b'G', ..] => "png",
+        [0xFF, 0xD8, 0xFF, ..] => "jpg",
+        [b'G', b'I', b'F', b'8', ..] => "gif",
+        _ if blob.starts_with(b"RIFF") && blob.get(8..12) == Some(&b"WEBP"[..]) => "webp",
+        [b'I', b'D', b'3', ..] | [0xFF, 0xFB, ..] => "mp3",
+        [b'O', b'g', b'g', b'S', ..] => "ogg",
+        _ => "bin",
+    }
+}
+
+#[inline]
+fn looks_like_html(text: &str) -> bool {
+    text.match_indices('<').any(|(i, _)| {
+        text[i + 1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/')
+    })
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Drop tags, <br> becomes a line break, decode the common entities
+pub fn strip_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        match rest[start..].find('>') {
+            Some(end) => {
+                let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+                if tag.starts_with("br") || tag == "/div" || tag == "/p" {
+                    out.push('\n');
+                }
+                rest = &rest[start + end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+// Convert autoruby's " 漢字[かんじ] " tokens
+pub fn ruby(text: &str, format: RubyFormat) -> String {
+    let japanese = |token: &str| {
+        token
+            .chars()
+            .next()
+            .is_some_and(|c| is_kanji(c) || is_kana(c))
+    };
+
+    let tokens: Vec<&str> = text.split(' ').collect();
+    let mut out = String::with_capacity(text.len());
+    for (i, token) in tokens.iter().enumerate() {
+        // autoruby's separators are the space before a reading and the one between it and
+        // the Japanese text after; other spaces belong to the text
+        if i > 0 {
+            let separator = ruby_token(token).is_some()
+                || (ruby_token(tokens[i - 1]).is_some() && japanese(token));
+            if matches!(format, RubyFormat::Anki) || !separator {
+                out.push(' ');
+            }
+        }
+
+        let Some((base, reading)) = ruby_token(token) else {
+            out.push_str(token);
+            continue;
+        };
+        match format {
+            RubyFormat::Anki => out.push_str(token),
+            RubyFormat::Html => out.push_str(&format!("<ruby>{base}<rt>{reading}</rt></ruby>")),
+            RubyFormat::Kana => out.push_str(reading),
+            RubyFormat::Plain => out.push_str(base),
+        }
+    }
+    out
+}
+
+// `漢字[かんじ]` → base and reading
+fn ruby_token(token: &str) -> Option<(&str, &str)> {
+    let start = token.find('[')?;
+    let rest = token.strip_suffix(']')?;
+    Some((&rest[..start], &rest[start + 1..]))
+}
+
+// Trailing line breaks are always dropped
+pub fn newline(text: &str, style: NewlineStyle) -> String {
+    let text = text
+        .replace("\r\n", "\n")
+        .replace("<br />", "\n")
+        .replace("<br/>", "\n")
+        .replace("<br>", "\n");
+    let text = text.trim_end_matches('\n');
+
+    match style {
+        NewlineStyle::Br => text.replace('\n', "<br>"),
+        NewlineStyle::Lf => text.to_string(),
+        NewlineStyle::Space => text.replace('\n', " "),
+        NewlineStyle::Div => text
+            .lines()
+            .map(|line| format!("<div>{line}</div>"))
+            .collect(),
+    }
+}
@@ -0,0 +1,296 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use regex::Regex;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    mine,
+    models::CardMetadata,
+    row_to_raw_hashmap,
+    validate::{self, is_kana, is_kanji},
+    value::Record,
+};
+
+// `<i>` in SRT, `{\an8}` overrides in both
+static TAGS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<[^>]*>|\{[^}]*\}").expect("valid regex"));
+
+// One subtitle line, times in milliseconds
+pub struct Cue {
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+// SRT, or ASS/SSA by extension
+pub fn read(filename: &str) -> Result<Vec<Cue>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(filename)?;
+    let content = content.trim_start_matches('\u{FEFF}').replace("\r\n", "\n");
+
+    let lower = filename.to_ascii_lowercase();
+    let cues = match lower.ends_with(".ass") || lower.ends_with(".ssa") {
+        true => read_ass(&content),
+        false => read_srt(&content),
+    };
+    if cues.is_empty() {
+        return Err(format!("{filename}: no subtitle lines").into());
+    }
+    Ok(cues)
+}
+
+fn read_srt(content: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    for block in content.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some((start, end)) = lines.next().and_then(|line| line.split_once("-->")) else {
+            continue;
+        };
+        let (Some(start), Some(end)) = (parse_time(start), parse_time(end)) else {
+            continue;
+        };
+
+        let text = clean(lines);
+        if !text.is_empty() {
+            cues.push(Cue { start, end, text });
+        }
+    }
+    cues
+}
+
+fn read_ass(content: &str) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    let mut events = false;
+    let mut format: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !events {
+            continue;
+        }
+
+        if let Some(columns) = line.strip_prefix("Format:") {
+            format = columns
+                .split(',')
+                .map(|column| column.trim().to_ascii_lowercase())
+                .collect();
+            continue;
+        }
+        // Comments are skipped
+        let Some(dialogue) = line.strip_prefix("Dialogue:") else {
+            continue;
+        };
+
+        // The text is last and may contain commas
+        let columns: Vec<&str> = dialogue.splitn(format.len().max(1), ',').collect();
+        let column = |name: &str| {
+            let i = format.iter().position(|column| column == name)?;
+            columns.get(i).map(|value| value.trim())
+        };
+        let (Some(start), Some(end), Some(text)) = (
+            column("start").and_then(parse_time),
+            column("end").and_then(parse_time),
+            column("text"),
+        ) else {
+            continue;
+        };
+
+        let text = text
+            .replace("\\N", "\n")
+            .replace("\\n", "\n")
+            .replace("\\h", " ");
+        let text = clean(text.lines());
+        if !text.is_empty() {
+            cues.push(Cue { start, end, text });
+        }
+    }
+    cues
+}
+
+// `00:01:02,345` (SRT) or `0:01:02.34` (ASS)
+fn parse_time(text: &str) -> Option<u64> {
+    let mut parts = text.trim().split(':');
+    let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next()?);
+    let (seconds, fraction) = seconds.split_once([',', '.']).unwrap_or((seconds, "0"));
+    let millis: String = fraction.chars().chain("000".chars()).take(3).collect();
+
+    Some(
+        hours.trim().parse::<u64>().ok()? * 3_600_000
+            + minutes.parse::<u64>().ok()? * 60_000
+            + seconds.parse::<u64>().ok()? * 1000
+            + millis.parse::<u64>().ok()?,
+    )
+}
+
+// `01:02:03.456`, as ffmpeg takes it
+pub fn timestamp(millis: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// Value of a `Source: clip` field, `/anime/ep01.srt@00:01:02.345-00:01:04.000`
+pub fn clip(filename: &str, cue: &Cue) -> String {
+    format!(
+        "{}@{}-{}",
+        filename,
+        timestamp(cue.start),
+        timestamp(cue.end)
+    )
+}
+
+// The file, start and end of a `Source: clip` value
+pub fn parse_clip(value: &str) -> Option<(&str, u64, u64)> {
+    let (file, span) = value.rsplit_once('@')?;
+    let (start, end) = span.split_once('-')?;
+    Some((file, parse_time(start)?, parse_time(end)?))
+}
+
+// Tags dropped; Japanese lines joined as they are, others with a space
+fn clean<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    let mut text = String::new();
+    for line in lines {
+        let line = TAGS.replace_all(line, "");
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let japanese = |c: Option<char>| c.is_some_and(|c| is_kanji(c) || is_kana(c));
+        if !text.is_empty() && !(japanese(text.chars().last()) && japanese(line.chars().next())) {
+            text.push(' ');
+        }
+        text.push_str(line);
+    }
+    text
+}
+
+// Records for `import-subs`: stored words without an example, or new words with `--mine`
+pub fn read_records(
+    conn: &rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    filename: &str,
+    new_words: bool,
+    known: Option<&str>,
+    limit: Option<usize>,
+) -> Result<Vec<Record>, Box<dyn std::error::Error>> {
+    let Some(sentence_field) = card_metadata
+        .fields
+        .iter()
+        .find(|field| field.metadata.example_for.is_some())
+    else {
+        return Err("no field has `Example For`".into());
+    };
+    let clip_field = card_metadata.fields.iter().find(|field| field.is_clip());
+    let cues = read(filename)?;
+    // Absolute, so `media extract` finds the video from any directory
+    let path = std::fs::canonicalize(filename)?;
+    let path = path.to_string_lossy();
+
+    let with_clip = |mut record: Record, cue: &Cue| {
+        if let Some(field) = clip_field {
+            record.insert(field.name.clone(), JsonValue::String(clip(&path, cue)));
+        }
+        record
+    };
+
+    if new_words {
+        let lines: Vec<&str> = cues.iter().map(|cue| cue.text.as_str()).collect();
+        return Ok(mine::candidates(conn, card_metadata, &lines, known, limit)?
+            .into_iter()
+            .map(|(word, i)| with_clip(mine::record(card_metadata, word, lines[i]), &cues[i]))
+            .collect());
+    }
+
+    let (sql, values) = card_metadata.get_all_cards_sql();
+    let mut stmt = conn.prepare(&sql)?;
+    let cards = stmt
+        .query_map(&*values.as_params(), row_to_raw_hashmap)?
+        .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
+
+    // Stored examples are kept
+    let source = sentence_field
+        .metadata
+        .example_for
+        .as_deref()
+        .unwrap_or_default();
+    let key = card_metadata.get_main_key();
+    let mut records = Vec::new();
+    for card in &cards {
+        if card
+            .get(&sentence_field.name)
+            .is_some_and(|sentence| !sentence.is_empty())
+        {
+            continue;
+        }
+        let Some(word) = card.get(source).filter(|word| !word.is_empty()) else {
+            continue;
+        };
+        let Some(cue) = cues
+            .iter()
+            .find(|cue| validate::contains_word(&cue.text, word))
+        else {
+            continue;
+        };
+
+        let mut record = Record::new();
+        record.insert(key.name.clone(), JsonValue::String(card[&key.name].clone()));
+        record.insert(
+            sentence_field.name.clone(),
+            JsonValue::String(cue.text.clone()),
+        );
+        records.push(with_clip(record, cue));
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_pads_every_part() {
+        assert_eq!(timestamp(0), "00:00:00.000");
+        assert_eq!(timestamp(62_345), "00:01:02.345");
+        assert_eq!(timestamp(3_723_004), "01:02:03.004");
+    }
+
+    #[test]
+    fn parse_time_reads_srt_and_ass() {
+        assert_eq!(parse_time("00:01:02,345"), Some(62_345));
+        assert_eq!(parse_time(" 0:01:02.34"), Some(62_340));
+        assert_eq!(parse_time("00:01:02"), Some(62_000));
+        assert_eq!(parse_time("01:02"), None);
+    }
+
+    #[test]
+    fn parse_clip_reads_what_clip_writes() {
+        let cue = Cue {
+            start: 62_345,
+            end: 64_000,
+            text: String::new(),
+        };
+        let value = clip("/anime/ep01.ja.srt", &cue);
+        assert_eq!(value, "/anime/ep01.ja.srt@00:01:02.345-00:01:04.000");
+        assert_eq!(
+            parse_clip(&value),
+            Some(("/anime/ep01.ja.srt", 62_345, 64_000))
+        );
+
+        // Only the last `@` splits the file from the times
+        assert_eq!(
+            parse_clip("/anime/@home/ep01.srt@00:00:01.000-00:00:02.500"),
+            Some(("/anime/@home/ep01.srt", 1000, 2500))
+        );
+        assert_eq!(parse_clip("/anime/ep01.srt"), None);
+        assert_eq!(parse_clip("/anime/ep01.srt@00:00:01.000"), None);
+        assert_eq!(parse_clip("/anime/ep01.srt@later-00:00:02.000"), None);
+    }
+}
@@ -0,0 +1,259 @@
+use std::path::Path;
+
+use crate::models::{CardMetadata, FieldSpec};
+
+// Built-in starter templates
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Preset {
+    Vocab,
+    Kanji,
+    SentenceMining,
+}
+
+impl Preset {
+    // Template and matching example cards
+    fn files(&self) -> (&'static str, &'static str) {
+        match self {
+            Preset::Vocab => (
+                include_str!("../presets/vocab.yaml"),
+                include_str!("../presets/vocab-cards.yaml"),
+            ),
+            Preset::Kanji => (
+                include_str!("../presets/kanji.yaml"),
+                include_str!("../presets/kanji-cards.yaml"),
+            ),
+            Preset::SentenceMining => (
+                include_str!("../presets/sentence-mining.yaml"),
+                include_str!("../presets/sentence-mining-cards.yaml"),
+            ),
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TemplateAction {
+    /// Print the resolved schema, DDL and derived fields, then any errors
+    Check {
+        #[arg(default_value = "jp-template.yaml")]
+        file: String,
+    },
+    /// Write a starter jp-template.yaml and cards.yaml
+    Init {
+        #[arg(short, long, value_enum, default_value_t = Preset::Vocab)]
+        preset: Preset,
+        #[arg(short, long, default_value = ".")]
+        dir: String,
+        /// Replace existing files
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+// Runs before the DB is opened, so it works without a usable template
+pub fn template(action: TemplateAction) -> Result<(), Box<dyn std::error::Error>> {
+    match action {
+        TemplateAction::Check { file } => check(&file),
+        TemplateAction::Init { preset, dir, force } => init(preset, &dir, force),
+    }
+}
+
+fn check(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let card_metadata = CardMetadata::parse_yaml(file)?;
+
+    println!(
+        "{} (version {}, {} fields)",
+        card_metadata.name,
+        card_metadata.version,
+        card_metadata.fields.len()
+    );
+
+    println!("\nColumns:");
+    for field in &card_metadata.fields {
+        println!(
+            "  {:<24} {:<10} {}",
+            field.name,
+            format!("{:?}", field.field_type),
+            describe(field).join(", ")
+        );
+    }
+
+    println!("\nDerived fields:");
+    let mut derived = false;
+    for field in &card_metadata.fields {
+        if let Some(source) = field.autoruby() {
+            println!("  {} ─Autoruby→ {}", source, field.name);
+            derived = true;
+        }
+        if let Some(source) = field.true_if_exists() {
+            println!("  {} ─True If Exists→ {}", source, field.name);
+            derived = true;
+        }
+        if let Some(source) = field.kanji_breakdown() {
+            println!("  {} ─Kanji Breakdown→ {}", source, field.name);
+            derived = true;
+        }
+        if let Some(source) = field.pitch_accent() {
+            match &field.metadata.reading {
+                Some(reading) => {
+                    println!("  {} + {} ─Pitch Accent→ {}", source, reading, field.name)
+                }
+                None => println!("  {} ─Pitch Accent→ {}", source, field.name),
+            }
+            derived = true;
+        }
+        if field.is_looked_up() {
+            let source = match &field.metadata.lookup {
+                Some(source) => source.as_str(),
+                None => "(key)",
+            };
+            println!(
+                "  {} ─{}→ {}",
+                source,
+                field.lookup_sources().join(" | "),
+                field.name
+            );
+            derived = true;
+        }
+    }
+    if !derived {
+        println!("  (none)");
+    }
+
+    for target in &card_metadata.targets {
+        println!(
+            "\nTarget '{}': {:?} → {}",
+            target.name, target.format, target.file
+        );
+        if let Some(sort) = &target.sort {
+            println!("  sorted by {}", sort);
+        }
+        if let Some(max_rank) = target.max_rank {
+            println!("  up to rank {}", max_rank);
+        }
+    }
+
+    // The table name comes from the environment
+    println!("\nDDL:");
+    if std::env::var("ANKIKAJI_DB").is_ok() {
+        println!("  {};", card_metadata.create_table_sql());
+        for sql in card_metadata.create_export_history_sql() {
+            println!("  {};", sql);
+        }
+    } else {
+        println!("  (set ANKIKAJI_DB to print the table DDL)");
+    }
+
+    let errors = card_metadata.check();
+    if !errors.is_empty() {
+        println!("\nErrors:");
+        for error in &errors {
+            println!("  ❌ {}", error);
+        }
+        return Err(format!("{} errors in {}", errors.len(), file).into());
+    }
+
+    println!("\n✅ {} is valid", file);
+    Ok(())
+}
+
+// Options of a field, as written in the template
+fn describe(field: &FieldSpec) -> Vec<String> {
+    let meta = &field.metadata;
+    let flags = [
+        (meta.primary_key, "Primary Key"),
+        (meta.key, "Key"),
+        (meta.auto_increment, "Auto Increment"),
+        (meta.not_null, "Not Null"),
+        (meta.unique, "Unique"),
+        (meta.image, "Image"),
+        (meta.audio, "Audio"),
+        (meta.kanji, "Kanji"),
+        (meta.kana_only, "Kana Only"),
+        (meta.contains_key, "Contains Key"),
+        (meta.file_exists, "File Exists"),
+        (meta.tags, "Tags"),
+    ];
+
+    let mut options: Vec<String> = flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name.to_string())
+        .collect();
+
+    if !field.enum_values().is_empty() {
+        options.push(format!("Values: {}", field.enum_values().join(", ")));
+    }
+    if field.is_dictionary() {
+        options.push(format!(
+            "Dictionary: {} ({:?}, {})",
+            field.dictionaries().join(", "),
+            meta.entry.unwrap_or_default(),
+            field.language()
+        ));
+    }
+    if !field.frequency_lists().is_empty() {
+        options.push(format!("Frequency: {}", field.frequency_lists().join(", ")));
+    }
+    if field.is_jlpt() {
+        options.push("JLPT".to_string());
+    }
+    if let Some(source) = &meta.example_for {
+        options.push(format!("Example For: {}", source));
+    }
+    if let Some(sentence) = &meta.translation_of {
+        options.push(format!("Translation Of: {}", sentence));
+    }
+    if let Some(format) = meta.format {
+        options.push(format!("Format: {:?}", format));
+    }
+    if let Some(policy) = field.merge_policy() {
+        options.push(format!("Merge: {:?}", policy));
+    }
+    if let Some(default) = field.default_value() {
+        options.push(format!("Default: {}", default));
+    }
+    if let Some(stamp) = field.now() {
+        options.push(format!("Now: {:?}", stamp));
+    }
+    if let Some(source) = meta.source {
+        options.push(format!("Source: {:?}", source));
+    }
+    if let Some(pattern) = &meta.pattern {
+        options.push(format!("Pattern: {}", pattern));
+    }
+    if let Some(min) = meta.min_length {
+        options.push(format!("Min Length: {}", min));
+    }
+    if let Some(max) = meta.max_length {
+        options.push(format!("Max Length: {}", max));
+    }
+    if let Some(severity) = meta.severity {
+        options.push(format!("Severity: {:?}", severity));
+    }
+
+    options
+}
+
+fn init(preset: Preset, dir: &str, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let (template, cards) = preset.files();
+
+    let dir = Path::new(dir);
+    let files = [
+        (dir.join("jp-template.yaml"), template),
+        (dir.join("cards.yaml"), cards),
+    ];
+
+    if !force {
+        if let Some((path, _)) = files.iter().find(|(path, _)| path.exists()) {
+            return Err(format!("{} already exists (use --force)", path.display()).into());
+        }
+    }
+
+    std::fs::create_dir_all(dir)?;
+    for (path, content) in &files {
+        std::fs::write(path, content)?;
+        println!("✅ Wrote {}", path.display());
+    }
+
+    Ok(())
+}
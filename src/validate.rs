@@ -0,0 +1,303 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use sea_query::Alias;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    models::{CardMetadata, FieldSpec},
+    row_to_raw_hashmap,
+    value::{FieldValue, Record},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Severity {
+    // Reported, the record is still imported
+    Warn,
+    // Reported, the record is skipped
+    Reject,
+}
+
+pub struct Violation {
+    pub field: String,
+    pub message: String,
+    pub severity: Severity,
+}
+
+// One field's rules from its metadata
+struct FieldRules {
+    name: String,
+    severity: Severity,
+    pattern: Option<Regex>,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    kanji: bool,
+    kana_only: bool,
+    contains_key: bool,
+    file_exists: bool,
+}
+
+// Validation rules of a template, compiled once per run
+pub struct Validator {
+    key: String,
+    fields: Vec<FieldRules>,
+    // `File Exists` paths are relative to it
+    media_dir: PathBuf,
+}
+
+impl Validator {
+    pub fn new(card_metadata: &CardMetadata) -> Result<Validator, String> {
+        let fields = card_metadata
+            .fields
+            .iter()
+            .map(FieldRules::new)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Called while checking the template, so no get_main_key here
+        let key = card_metadata
+            .fields
+            .iter()
+            .find(|field| field.is_key())
+            .map(|field| field.name.clone())
+            .unwrap_or_default();
+
+        Ok(Validator {
+            key,
+            fields,
+            media_dir: PathBuf::from(card_metadata.media_dir()),
+        })
+    }
+
+    // Empty values are left to `Not Null`
+    pub fn check(&self, fields: &[Alias], values: &[FieldValue]) -> Vec<Violation> {
+        let get = |name: &str| {
+            fields
+                .iter()
+                .position(|alias| alias.0 == name)
+                .map(|pos| &values[pos])
+                .filter(|value| !value.is_empty())
+        };
+        let key = get(&self.key).map(|value| value.to_string());
+
+        let mut violations = Vec::new();
+        for rules in &self.fields {
+            let Some(value) = get(&rules.name) else {
+                continue;
+            };
+
+            for message in rules.check(&value.to_string(), key.as_deref(), &self.media_dir) {
+                violations.push(Violation {
+                    field: rules.name.clone(),
+                    message,
+                    severity: rules.severity,
+                });
+            }
+        }
+        violations
+    }
+}
+
+impl FieldRules {
+    fn new(field: &FieldSpec) -> Result<FieldRules, String> {
+        let rules = &field.metadata;
+
+        let pattern = rules
+            .pattern
+            .as_ref()
+            .map(|pattern| {
+                // The whole value has to match
+                Regex::new(&format!("^(?:{pattern})$"))
+                    .map_err(|e| format!("field '{}': invalid pattern: {e}", field.name))
+            })
+            .transpose()?;
+
+        Ok(FieldRules {
+            name: field.name.clone(),
+            severity: rules.severity.unwrap_or(Severity::Reject),
+            pattern,
+            min_length: rules.min_length,
+            max_length: rules.max_length,
+            kanji: rules.kanji,
+            kana_only: rules.kana_only,
+            contains_key: rules.contains_key,
+            file_exists: rules.file_exists,
+        })
+    }
+
+    fn check(&self, value: &str, key: Option<&str>, media_dir: &Path) -> Vec<String> {
+        let mut messages = Vec::new();
+        let length = value.chars().count();
+
+        if let Some(pattern) = &self.pattern {
+            if !pattern.is_match(value) {
+                messages.push(format!("doesn't match {}", pattern.as_str()));
+            }
+        }
+        if let Some(min) = self.min_length {
+            if length < min {
+                messages.push(format!("shorter than {min} characters"));
+            }
+        }
+        if let Some(max) = self.max_length {
+            if length > max {
+                messages.push(format!("longer than {max} characters"));
+            }
+        }
+        if self.kanji && !value.chars().any(is_kanji) {
+            messages.push("contains no kanji".to_string());
+        }
+        if self.kana_only && !value.chars().all(|c| is_kana(c) || c.is_whitespace()) {
+            messages.push("contains more than kana".to_string());
+        }
+        if let (true, Some(key)) = (self.contains_key, key) {
+            if !contains_word(value, key) {
+                messages.push(format!("doesn't contain '{key}'"));
+            }
+        }
+        if self.file_exists {
+            // Absolute paths stay as they are
+            let path = media_dir.join(media_path(value));
+            if !path.exists() {
+                messages.push(format!("file '{}' not found", path.display()));
+            }
+        }
+
+        messages
+    }
+}
+
+#[inline]
+pub fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '々')
+}
+
+#[inline]
+pub fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{309F}' | '\u{30A0}'..='\u{30FF}')
+}
+
+// Inflected verbs and adjectives count: 掲げる matches 掲げて
+pub fn contains_word(text: &str, word: &str) -> bool {
+    text.contains(word) || text.contains(stem(word))
+}
+
+// 掲げる → 掲げ, other words as they are
+pub fn stem(word: &str) -> &str {
+    let mut stem = word.chars();
+    match stem.next_back() {
+        Some(last) if is_kana(last) && stem.as_str().chars().any(is_kanji) => stem.as_str(),
+        _ => word,
+    }
+}
+
+// File name inside `<img src="…">` or `[sound:…]`, otherwise the value itself
+pub fn media_path(value: &str) -> &str {
+    if let Some((_, rest)) = value.split_once("src=\"") {
+        return rest.split('"').next().unwrap_or(rest);
+    }
+    if let Some(rest) = value.strip_prefix("[sound:") {
+        return rest.trim_end_matches(']');
+    }
+    value.trim()
+}
+
+// Print the violations of one record, true if it has to be skipped
+pub fn report(word: &str, violations: &[Violation]) -> bool {
+    for violation in violations {
+        let mark = match violation.severity {
+            Severity::Warn => "⚠️",
+            Severity::Reject => "❌",
+        };
+        eprintln!(
+            "{} {}: field '{}' {}",
+            mark, word, violation.field, violation.message
+        );
+    }
+
+    violations
+        .iter()
+        .any(|violation| violation.severity == Severity::Reject)
+}
+
+// Check the cards in a file (without importing) or every stored card
+pub fn validate(
+    conn: &rusqlite::Connection,
+    card_metadata: &CardMetadata,
+    records: Option<&[Record]>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let validator = Validator::new(card_metadata)?;
+    let key = card_metadata.get_main_key();
+
+    // Per card: its key and violations, None if it can't be typed at all
+    let mut results: Vec<(String, Option<Vec<Violation>>)> = Vec::new();
+    match records {
+        Some(records) => {
+            for record in records {
+                let word = match record.get(&key.name) {
+                    Some(JsonValue::String(word)) => word.clone(),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                };
+                match card_metadata.get_data_from_record(record) {
+                    Ok((fields, values, _)) => {
+                        results.push((word, Some(validator.check(&fields, &values))))
+                    }
+                    Err(e) => {
+                        eprintln!("❌ {}: {}", word, e);
+                        results.push((word, None));
+                    }
+                }
+            }
+        }
+        None => {
+            let (sql, values) = card_metadata.get_all_cards_sql();
+            let mut stmt = conn.prepare(&sql)?;
+            let cards = stmt
+                .query_map(&*values.as_params(), row_to_raw_hashmap)?
+                .collect::<rusqlite::Result<Vec<HashMap<String, String>>>>()?;
+
+            for card in &cards {
+                let (fields, values): (Vec<Alias>, Vec<FieldValue>) = card_metadata
+                    .fields
+                    .iter()
+                    .filter_map(|field| {
+                        let value = card.get(&field.name).filter(|value| !value.is_empty())?;
+                        // Stored values were typed on the way in
+                        let value = FieldValue::parse(field, value).ok()?;
+                        Some((field.get_alias(), value))
+                    })
+                    .unzip();
+                let word = card.get(&key.name).cloned().unwrap_or_default();
+                results.push((word, Some(validator.check(&fields, &values))));
+            }
+        }
+    }
+
+    let mut rejected = 0;
+    let mut warned = 0;
+    for (word, violations) in &results {
+        match violations {
+            None => rejected += 1,
+            Some(violations) if report(word, violations) => rejected += 1,
+            Some(violations) if !violations.is_empty() => warned += 1,
+            Some(_) => {}
+        }
+    }
+
+    println!(
+        "Checked {} cards: {} rejected, {} with warnings",
+        results.len(),
+        rejected,
+        warned
+    );
+
+    // Fails the command, so scripts can stop on it
+    if rejected > 0 {
+        return Err(format!("{rejected} cards rejected").into());
+    }
+    Ok(())
+}
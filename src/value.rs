@@ -0,0 +1,270 @@
+use std::{collections::HashMap, fmt};
+
+use sea_query::Expr;
+use serde_json::Value as JsonValue;
+
+use crate::models::{FieldSpec, FieldType};
+
+// One card as read from cards.yaml or JSON, before typing
+pub type Record = HashMap<String, JsonValue>;
+
+// A field value typed after its FieldType
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    // Explicitly cleared (`null` in the input)
+    Null,
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    // Also Date, DateTime and Enum, normalized
+    Text(String),
+    // List and Json, stored as JSON text
+    Json(JsonValue),
+}
+
+#[derive(Debug)]
+pub struct ValueError {
+    pub field: String,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "field '{}': expected {}, found {}",
+            self.field, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+impl FieldValue {
+    // Input value (YAML or JSON) converted to the field's type
+    pub fn from_input(field: &FieldSpec, value: &JsonValue) -> Result<FieldValue, ValueError> {
+        let mismatch = || ValueError {
+            field: field.name.clone(),
+            expected: match field.field_type {
+                FieldType::Enum => format!("one of {}", field.enum_values().join(", ")),
+                FieldType::Date => "a date (YYYY-MM-DD)".to_string(),
+                FieldType::DateTime => "a date and time (YYYY-MM-DD HH:MM[:SS])".to_string(),
+                ref field_type => format!("{:?}", field_type),
+            },
+            found: value.to_string(),
+        };
+
+        let converted = match (&field.field_type, value) {
+            (_, JsonValue::Null) => FieldValue::Null,
+
+            (FieldType::Integer, JsonValue::Number(n)) => {
+                FieldValue::Integer(n.as_i64().ok_or_else(mismatch)?)
+            }
+            (FieldType::Integer, JsonValue::String(s)) => {
+                FieldValue::Integer(s.trim().parse().map_err(|_| mismatch())?)
+            }
+
+            (FieldType::Float, JsonValue::Number(n)) => {
+                FieldValue::Float(n.as_f64().ok_or_else(mismatch)?)
+            }
+            (FieldType::Float, JsonValue::String(s)) => match s.trim().parse::<f64>() {
+                Ok(f) if f.is_finite() => FieldValue::Float(f),
+                _ => return Err(mismatch()),
+            },
+
+            (FieldType::Boolean, JsonValue::Bool(b)) => FieldValue::Boolean(*b),
+            (FieldType::Boolean, JsonValue::Number(n)) => match n.as_i64() {
+                Some(0) => FieldValue::Boolean(false),
+                Some(1) => FieldValue::Boolean(true),
+                _ => return Err(mismatch()),
+            },
+            (FieldType::Boolean, JsonValue::String(s)) => {
+                match s.trim().to_ascii_lowercase().as_str() {
+                    "true" | "yes" | "1" => FieldValue::Boolean(true),
+                    "false" | "no" | "0" => FieldValue::Boolean(false),
+                    _ => return Err(mismatch()),
+                }
+            }
+
+            (FieldType::Date, JsonValue::String(s)) => {
+                FieldValue::Text(parse_date(s.trim()).ok_or_else(mismatch)?)
+            }
+            (FieldType::DateTime, JsonValue::String(s)) => {
+                FieldValue::Text(parse_date_time(s.trim()).ok_or_else(mismatch)?)
+            }
+
+            (FieldType::Enum, JsonValue::String(_) | JsonValue::Number(_)) => {
+                let text = match value {
+                    JsonValue::String(s) => s.trim().to_string(),
+                    value => value.to_string(),
+                };
+                if !field.enum_values().contains(&text.as_str()) {
+                    return Err(mismatch());
+                }
+                FieldValue::Text(text)
+            }
+
+            (FieldType::List, JsonValue::Array(_)) => FieldValue::Json(value.clone()),
+            // As stored, or `a, b, c`
+            (FieldType::List, JsonValue::String(s)) => match serde_json::from_str(s) {
+                Ok(JsonValue::Array(items)) => FieldValue::Json(JsonValue::Array(items)),
+                _ => FieldValue::Json(JsonValue::Array(
+                    s.split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(JsonValue::from)
+                        .collect(),
+                )),
+            },
+
+            // Text that is JSON (e.g. as stored) is taken as such
+            (FieldType::Json, JsonValue::String(s)) => {
+                FieldValue::Json(serde_json::from_str(s).unwrap_or_else(|_| value.clone()))
+            }
+            (FieldType::Json, _) => FieldValue::Json(value.clone()),
+
+            (FieldType::String | FieldType::Text, JsonValue::String(s)) => {
+                FieldValue::Text(s.clone())
+            }
+            (FieldType::String | FieldType::Text, JsonValue::Number(n)) => {
+                FieldValue::Text(n.to_string())
+            }
+            // Tags, space separated like Anki
+            (FieldType::String | FieldType::Text, JsonValue::Array(items)) => FieldValue::Text(
+                items
+                    .iter()
+                    .map(|item| match item {
+                        JsonValue::String(s) => s.clone(),
+                        item => item.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            (FieldType::String | FieldType::Text, JsonValue::Object(_)) => {
+                FieldValue::Text(value.to_string())
+            }
+
+            _ => return Err(mismatch()),
+        };
+
+        Ok(converted)
+    }
+
+    // Stored or typed-in text converted to the field's type
+    #[inline]
+    pub fn parse(field: &FieldSpec, text: &str) -> Result<FieldValue, ValueError> {
+        FieldValue::from_input(field, &JsonValue::String(text.to_string()))
+    }
+
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        matches!(self, FieldValue::Null)
+    }
+
+    // Null, blank text and empty lists count as empty
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        match self {
+            FieldValue::Null => true,
+            FieldValue::Text(s) => s.is_empty(),
+            FieldValue::Json(JsonValue::Array(items)) => items.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldValue::Null => Ok(()),
+            FieldValue::Integer(i) => write!(f, "{i}"),
+            FieldValue::Float(x) => write!(f, "{x}"),
+            FieldValue::Boolean(b) => write!(f, "{}", *b as i64),
+            FieldValue::Text(s) => write!(f, "{s}"),
+            FieldValue::Json(json) => write!(f, "{json}"),
+        }
+    }
+}
+
+impl From<&FieldValue> for Expr {
+    fn from(value: &FieldValue) -> Self {
+        match value {
+            FieldValue::Null => Expr::value(Option::<String>::None),
+            FieldValue::Integer(i) => Expr::value(*i),
+            FieldValue::Float(x) => Expr::value(*x),
+            FieldValue::Boolean(b) => Expr::value(*b),
+            FieldValue::Text(s) => Expr::value(s.clone()),
+            FieldValue::Json(json) => Expr::value(json.to_string()),
+        }
+    }
+}
+
+// `YYYY-MM-DD`, also accepts single digit month and day
+fn parse_date(text: &str) -> Option<String> {
+    let mut parts = text.splitn(3, ['-', '/']);
+    let year: u32 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return None,
+    };
+    if !(1000..=9999).contains(&year) || !(1..=days).contains(&day) {
+        return None;
+    }
+
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+// `YYYY-MM-DD HH:MM[:SS]` (or with `T`), stored the way SQLite's datetime() writes it
+fn parse_date_time(text: &str) -> Option<String> {
+    let (date, time) = text.split_once([' ', 'T'])?;
+    let date = parse_date(date)?;
+
+    let mut parts = time.trim_end_matches('Z').splitn(3, ':');
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let minute: u32 = parts.next()?.parse().ok()?;
+    let second: u32 = match parts.next() {
+        // Fractions are dropped
+        Some(second) => second.split('.').next()?.parse().ok()?,
+        None => 0,
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+
+    Some(format!("{date} {hour:02}:{minute:02}:{second:02}"))
+}
+
+// Current UTC time as `YYYY-MM-DD HH:MM:SS`, like SQLite's CURRENT_TIMESTAMP
+pub fn now() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default();
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+
+    // Days since 1970-01-01 to a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
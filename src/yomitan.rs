@@ -0,0 +1,236 @@
+use std::{collections::HashMap, fs::File, io::Read};
+
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    dictionary::{Entry, Sense},
+    render::escape_html,
+};
+
+// index.json of a Yomitan / Yomichan dictionary
+#[derive(Deserialize)]
+struct Index {
+    title: String,
+}
+
+// Title and entries of a dictionary zip, entries in score order
+pub fn read_zip(filename: &str) -> Result<(String, Vec<(i64, Entry)>), Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(File::open(filename)?)?;
+
+    let index: Index = serde_json::from_str(&read_file(&mut archive, "index.json")?)
+        .map_err(|e| format!("{filename}: index.json: {e}"))?;
+
+    // term_bank_1.json, term_bank_2.json, … in order
+    let mut banks: Vec<(u32, String)> = archive
+        .file_names()
+        .filter_map(|name| {
+            let number = name.strip_prefix("term_bank_")?.strip_suffix(".json")?;
+            Some((number.parse().ok()?, name.to_string()))
+        })
+        .collect();
+    banks.sort();
+    if banks.is_empty() {
+        return Err(format!("{filename}: no term_bank_*.json, not a term dictionary").into());
+    }
+
+    // Rows of one headword (same expression, reading and sequence) are its senses
+    let mut entries: Vec<(f64, Entry)> = Vec::new();
+    let mut positions: HashMap<(String, String, i64), usize> = HashMap::new();
+    for (_, name) in &banks {
+        let rows: Vec<JsonValue> = serde_json::from_str(&read_file(&mut archive, name)?)
+            .map_err(|e| format!("{filename}: {name}: {e}"))?;
+
+        for row in &rows {
+            let Some(term) = Term::from_row(row) else {
+                eprintln!("⚠️ {}: skipped malformed row {}", name, row);
+                continue;
+            };
+
+            let pos = *positions
+                .entry((term.expression.clone(), term.reading.clone(), term.sequence))
+                .or_insert_with(|| {
+                    let reading = match term.reading.is_empty() {
+                        true => term.expression.clone(),
+                        false => term.reading.clone(),
+                    };
+                    entries.push((
+                        term.score,
+                        Entry {
+                            forms: vec![(term.expression.clone(), term.common)],
+                            readings: vec![(reading, term.common)],
+                            senses: Vec::new(),
+                        },
+                    ));
+                    entries.len() - 1
+                });
+
+            let (score, entry) = &mut entries[pos];
+            *score = score.max(term.score);
+            if !term.glosses.is_empty() {
+                entry.senses.push(Sense {
+                    pos: term.tags,
+                    // Unknown, matches any `Language`
+                    lang: String::new(),
+                    glosses: term.glosses,
+                });
+            }
+        }
+    }
+
+    // Higher scores first, lookups take the lowest id
+    entries.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    let entries = entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, entry))| (i as i64 + 1, entry))
+        .collect();
+
+    Ok((index.title, entries))
+}
+
+fn read_file(
+    archive: &mut zip::ZipArchive<File>,
+    name: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut content = String::new();
+    archive.by_name(name)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+// One term_bank row:
+// [expression, reading, definition tags, rules, score, glossary, sequence, term tags]
+struct Term {
+    expression: String,
+    reading: String,
+    tags: Vec<String>,
+    score: f64,
+    glosses: Vec<String>,
+    sequence: i64,
+    common: bool,
+}
+
+impl Term {
+    fn from_row(row: &JsonValue) -> Option<Term> {
+        let row = row.as_array()?;
+        let text = |i: usize| row.get(i).and_then(JsonValue::as_str).unwrap_or_default();
+        let words = |i: usize| {
+            text(i)
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        let glosses = row
+            .get(5)?
+            .as_array()?
+            .iter()
+            .filter_map(glossary_html)
+            .filter(|gloss| !gloss.is_empty())
+            .collect();
+
+        Some(Term {
+            expression: row.first()?.as_str()?.to_string(),
+            reading: text(1).to_string(),
+            tags: words(2),
+            score: row.get(4).and_then(JsonValue::as_f64).unwrap_or_default(),
+            glosses,
+            sequence: row.get(6).and_then(JsonValue::as_i64).unwrap_or_default(),
+            // JMdict based dictionaries tag popular terms with `P`
+            common: words(7).iter().any(|tag| tag == "P" || tag == "common"),
+        })
+    }
+}
+
+// A glossary item as HTML; images and deinflections are left out
+fn glossary_html(item: &JsonValue) -> Option<String> {
+    match item {
+        JsonValue::String(text) => Some(escape_html(text)),
+        JsonValue::Object(object) => match object.get("type")?.as_str()? {
+            "text" => Some(escape_html(object.get("text")?.as_str()?)),
+            "structured-content" => Some(structured_html(object.get("content")?)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Tags structured content may use, anything else keeps only its content
+const STRUCTURED_TAGS: [&str; 16] = [
+    "br", "ruby", "rt", "rp", "table", "thead", "tbody", "tfoot", "tr", "td", "th", "span", "div",
+    "ol", "ul", "li",
+];
+
+// Yomitan structured content to HTML
+fn structured_html(content: &JsonValue) -> String {
+    match content {
+        JsonValue::String(text) => escape_html(text),
+        JsonValue::Array(items) => items.iter().map(structured_html).collect(),
+        JsonValue::Object(node) => {
+            let inner = node.get("content").map(structured_html).unwrap_or_default();
+            let tag = node
+                .get("tag")
+                .and_then(JsonValue::as_str)
+                .unwrap_or_default();
+
+            match tag {
+                "br" => "<br>".to_string(),
+                // Images live in the zip, not in the card
+                "img" => String::new(),
+                "a" => match node.get("href").and_then(JsonValue::as_str) {
+                    // `?query=…` links point inside Yomitan
+                    Some(href) if href.starts_with("http") => {
+                        format!("<a href=\"{}\">{}</a>", escape_html(href), inner)
+                    }
+                    _ => inner,
+                },
+                "details" | "summary" => inner,
+                tag if STRUCTURED_TAGS.contains(&tag) => {
+                    format!("<{tag}{}>{inner}</{tag}>", attributes(node))
+                }
+                _ => inner,
+            }
+        }
+        _ => String::new(),
+    }
+}
+
+// `style` (camelCase keys) and `lang`, as HTML attributes
+fn attributes(node: &serde_json::Map<String, JsonValue>) -> String {
+    let mut attributes = String::new();
+
+    if let Some(JsonValue::Object(style)) = node.get("style") {
+        let css: Vec<String> = style
+            .iter()
+            .map(|(property, value)| {
+                let value = match value {
+                    JsonValue::String(text) => text.clone(),
+                    value => value.to_string(),
+                };
+                format!("{}: {}", kebab_case(property), value)
+            })
+            .collect();
+        if !css.is_empty() {
+            attributes.push_str(&format!(" style=\"{}\"", escape_html(&css.join("; "))));
+        }
+    }
+    if let Some(lang) = node.get("lang").and_then(JsonValue::as_str) {
+        attributes.push_str(&format!(" lang=\"{}\"", escape_html(lang)));
+    }
+
+    attributes
+}
+
+// fontWeight → font-weight
+fn kebab_case(property: &str) -> String {
+    let mut out = String::with_capacity(property.len() + 4);
+    for c in property.chars() {
+        if c.is_ascii_uppercase() {
+            out.push('-');
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}